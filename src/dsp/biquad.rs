@@ -0,0 +1,134 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl Coefficients {
+    pub const IDENTITY: Coefficients = Coefficients {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    // Designs from the RBJ audio EQ cookbook
+    pub fn peaking(sample_rate: u32, frequency: f32, gain_db: f32, q: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+
+        Self::normalize(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn low_shelf(sample_rate: u32, frequency: f32, gain_db: f32, q: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+        let sqrt = 2.0 * a.sqrt() * alpha;
+
+        Self::normalize(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + sqrt,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt,
+            ],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: u32, frequency: f32, gain_db: f32, q: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+        let sqrt = 2.0 * a.sqrt() * alpha;
+
+        Self::normalize(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + sqrt,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt,
+            ],
+        )
+    }
+
+    pub fn low_pass(sample_rate: u32, frequency: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+
+        Self::normalize(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn high_pass(sample_rate: u32, frequency: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, frequency, q);
+
+        Self::normalize(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn prewarp(sample_rate: u32, frequency: f32, q: f32) -> (f32, f32) {
+        let nyquist = sample_rate as f32 / 2.0;
+        let w0 = std::f32::consts::TAU * frequency.clamp(1.0, nyquist * 0.99) / sample_rate as f32;
+
+        (w0.cos(), w0.sin() / (2.0 * q.max(0.01)))
+    }
+
+    fn normalize(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+        }
+    }
+}
+
+// Transposed direct form II, one instance per channel
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    coefficients: Coefficients,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub fn new(coefficients: Coefficients) -> Self {
+        Self {
+            coefficients,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn set_coefficients(&mut self, coefficients: Coefficients) {
+        self.coefficients = coefficients;
+    }
+
+    #[inline]
+    pub fn process(&mut self, input: f32) -> f32 {
+        let Coefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        let output = b0 * input + self.z1;
+        self.z1 = b1 * input - a1 * output + self.z2;
+        self.z2 = b2 * input - a2 * output;
+
+        output
+    }
+}
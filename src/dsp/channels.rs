@@ -0,0 +1,127 @@
+use super::AudioEffect;
+
+// Routing applied in order: custom matrix, swap, mono downmix, balance
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelMap {
+    // Rows are output channels, columns are input channels
+    pub matrix: Option<Vec<Vec<f32>>>,
+    pub swap: bool,
+    pub mono: bool,
+    // -1.0 is fully left, 1.0 is fully right
+    pub balance: f32,
+}
+
+impl ChannelMap {
+    fn is_identity(&self) -> bool {
+        self.matrix.is_none() && !self.swap && !self.mono && self.balance == 0.0
+    }
+
+    fn build(&self, channels: usize) -> Vec<Vec<f32>> {
+        let mut matrix: Vec<Vec<f32>> = match self.matrix {
+            Some(ref matrix) if matrix.len() == channels => matrix
+                .iter()
+                .map(|row| {
+                    let mut row = row.clone();
+                    row.resize(channels, 0.0);
+                    row
+                })
+                .collect(),
+            _ => (0..channels)
+                .map(|out| (0..channels).map(|i| (i == out) as u8 as f32).collect())
+                .collect(),
+        };
+
+        if channels != 2 {
+            return matrix;
+        }
+
+        if self.swap {
+            matrix.swap(0, 1);
+        }
+
+        if self.mono {
+            let row: Vec<f32> = (0..channels)
+                .map(|i| (matrix[0][i] + matrix[1][i]) / 2.0)
+                .collect();
+            matrix = vec![row.clone(), row];
+        }
+
+        let balance = self.balance.clamp(-1.0, 1.0);
+        let gains = [(1.0 - balance).min(1.0), (1.0 + balance).min(1.0)];
+        for (row, gain) in matrix.iter_mut().zip(gains) {
+            for value in row.iter_mut() {
+                *value *= gain;
+            }
+        }
+
+        matrix
+    }
+}
+
+#[derive(Default)]
+pub struct ChannelMapper {
+    map: ChannelMap,
+    matrix: Vec<Vec<f32>>,
+    frame: Vec<f32>,
+}
+
+impl ChannelMapper {
+    pub fn new(map: ChannelMap) -> Self {
+        Self {
+            map,
+            ..Default::default()
+        }
+    }
+
+    pub fn map(&self) -> &ChannelMap {
+        &self.map
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.map.is_identity()
+    }
+
+    pub fn set_map(&mut self, map: ChannelMap) {
+        self.map = map;
+        self.matrix.clear();
+    }
+}
+
+impl AudioEffect for ChannelMapper {
+    fn name(&self) -> &str {
+        "Channel mapper"
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: u16, _sample_rate: u32) {
+        if self.map.is_identity() {
+            return;
+        }
+
+        let channels = channels as usize;
+        if self.matrix.len() != channels {
+            self.matrix = self.map.build(channels);
+        }
+
+        for frame in samples.chunks_exact_mut(channels) {
+            self.frame.clear();
+            self.frame.extend_from_slice(frame);
+
+            for (sample, row) in frame.iter_mut().zip(self.matrix.iter()) {
+                *sample = row.iter().zip(self.frame.iter()).map(|(a, b)| a * b).sum();
+            }
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        let mut map = self.map.clone();
+        match name {
+            "balance" => map.balance = value,
+            "swap" => map.swap = value != 0.0,
+            "mono" => map.mono = value != 0.0,
+            _ => return false,
+        }
+        self.set_map(map);
+
+        true
+    }
+}
@@ -0,0 +1,359 @@
+use std::{collections::VecDeque, time::Duration};
+
+use super::AudioEffect;
+
+fn to_db(value: f32) -> f32 {
+    20.0 * value.max(1e-9).log10()
+}
+
+fn to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// One pole smoothing coefficient reaching ~63% after `time`
+fn coefficient(time: Duration, sample_rate: u32) -> f32 {
+    let samples = time.as_secs_f32() * sample_rate as f32;
+    match samples > 0.0 {
+        true => (-1.0 / samples).exp(),
+        false => 0.0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorSettings {
+    // dBFS
+    pub threshold: f32,
+    pub ratio: f32,
+    pub attack: Duration,
+    pub release: Duration,
+    // dB
+    pub makeup: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self {
+            threshold: -24.0,
+            ratio: 4.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(200),
+            makeup: 6.0,
+        }
+    }
+}
+
+impl std::fmt::Display for CompressorSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Compressor(threshold: {}, ratio: {}, attack: {:?}, release: {:?}, makeup: {})",
+            self.threshold, self.ratio, self.attack, self.release, self.makeup
+        )
+    }
+}
+
+// Feed-forward compressor with the channels linked so the image doesn't shift
+pub struct Compressor {
+    settings: CompressorSettings,
+    sample_rate: u32,
+    attack: f32,
+    release: f32,
+    // Current gain reduction in dB
+    reduction: f32,
+}
+
+impl Compressor {
+    pub fn new(settings: CompressorSettings) -> Self {
+        Self {
+            settings,
+            sample_rate: 0,
+            attack: 0.0,
+            release: 0.0,
+            reduction: 0.0,
+        }
+    }
+
+    pub fn settings(&self) -> CompressorSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: CompressorSettings) {
+        self.settings = settings;
+        self.sample_rate = 0;
+    }
+}
+
+impl AudioEffect for Compressor {
+    fn name(&self) -> &str {
+        "Compressor"
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: u16, sample_rate: u32) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.attack = coefficient(self.settings.attack, sample_rate);
+            self.release = coefficient(self.settings.release, sample_rate);
+        }
+
+        let CompressorSettings {
+            threshold,
+            ratio,
+            makeup,
+            ..
+        } = self.settings;
+        let slope = 1.0 - 1.0 / ratio.max(1.0);
+
+        for frame in samples.chunks_exact_mut(channels as usize) {
+            let level = to_db(
+                frame
+                    .iter()
+                    .fold(0.0, |peak, sample| sample.abs().max(peak)),
+            );
+            let target = (level - threshold).max(0.0) * slope;
+
+            let coefficient = match target > self.reduction {
+                true => self.attack,
+                false => self.release,
+            };
+            self.reduction = target + (self.reduction - target) * coefficient;
+
+            let gain = to_gain(makeup - self.reduction);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        let mut settings = self.settings;
+        match name {
+            "threshold" => settings.threshold = value,
+            "ratio" => settings.ratio = value,
+            "attack" => settings.attack = Duration::from_secs_f32(value.max(0.0) / 1000.0),
+            "release" => settings.release = Duration::from_secs_f32(value.max(0.0) / 1000.0),
+            "makeup" => settings.makeup = value,
+            _ => return false,
+        }
+        self.set_settings(settings);
+
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterSettings {
+    // dBTP
+    pub ceiling: f32,
+    pub lookahead: Duration,
+    pub release: Duration,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            ceiling: -1.0,
+            lookahead: Duration::from_micros(1500),
+            release: Duration::from_millis(100),
+        }
+    }
+}
+
+impl std::fmt::Display for LimiterSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Limiter(ceiling: {}, lookahead: {:?}, release: {:?})",
+            self.ceiling, self.lookahead, self.release
+        )
+    }
+}
+
+// Lookahead brickwall limiter working on estimated true peaks
+//
+// The signal is delayed by the lookahead, the gain each frame needs is taken
+// as the minimum over the lookahead window and then averaged over it, which
+// ramps the gain down in time and never lets it exceed what a peak needs
+pub struct Limiter {
+    settings: LimiterSettings,
+    sample_rate: u32,
+    channels: usize,
+    lookahead: usize,
+    release: f32,
+    // Last input frames per channel for the inter-sample peak estimate
+    history: Vec<[f32; 3]>,
+    delay: VecDeque<f32>,
+    required: VecDeque<f32>,
+    minimums: VecDeque<f32>,
+    sum: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    pub fn new(settings: LimiterSettings) -> Self {
+        Self {
+            settings,
+            sample_rate: 0,
+            channels: 0,
+            lookahead: 0,
+            release: 0.0,
+            history: Vec::new(),
+            delay: VecDeque::new(),
+            required: VecDeque::new(),
+            minimums: VecDeque::new(),
+            sum: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    pub fn settings(&self) -> LimiterSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: LimiterSettings) {
+        self.settings = settings;
+        self.sample_rate = 0;
+    }
+
+    fn setup(&mut self, channels: usize, sample_rate: u32) {
+        let lookahead =
+            ((self.settings.lookahead.as_secs_f32() * sample_rate as f32) as usize).max(1);
+
+        // Keep the delayed audio when only the release changed
+        if lookahead != self.lookahead || channels != self.channels {
+            self.history = vec![[0.0; 3]; channels];
+            self.delay = VecDeque::from(vec![0.0; lookahead * channels]);
+            self.required = VecDeque::from(vec![1.0; lookahead + 1]);
+            self.minimums = VecDeque::from(vec![1.0; lookahead + 1]);
+            self.sum = (lookahead + 1) as f32;
+        }
+
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.lookahead = lookahead;
+        self.release = coefficient(self.settings.release, sample_rate);
+    }
+
+    // Catmull-Rom interpolation between the two middle samples
+    fn true_peak(history: &[f32; 3], sample: f32) -> f32 {
+        let [p0, p1, p2] = *history;
+        let p3 = sample;
+
+        [0.25f32, 0.5, 0.75]
+            .iter()
+            .map(|t| {
+                let t2 = t * t;
+                let t3 = t2 * t;
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+            })
+            .fold(p2.abs().max(p3.abs()), |peak, value| peak.max(value.abs()))
+    }
+}
+
+impl AudioEffect for Limiter {
+    fn name(&self) -> &str {
+        "Limiter"
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: u16, sample_rate: u32) {
+        let channels = channels as usize;
+        if self.sample_rate != sample_rate || self.channels != channels {
+            self.setup(channels, sample_rate);
+        }
+
+        let ceiling = to_gain(self.settings.ceiling);
+        for frame in samples.chunks_exact_mut(channels) {
+            let mut peak: f32 = 0.0;
+            for (sample, history) in frame.iter().zip(self.history.iter_mut()) {
+                peak = peak.max(Self::true_peak(history, *sample));
+                *history = [history[1], history[2], *sample];
+            }
+
+            self.required.pop_front();
+            self.required.push_back(match peak > ceiling {
+                true => ceiling / peak,
+                false => 1.0,
+            });
+
+            let minimum = self.required.iter().fold(1.0f32, |a, b| a.min(*b));
+            self.sum += minimum - self.minimums.pop_front().unwrap_or(1.0);
+            self.minimums.push_back(minimum);
+            let target = (self.sum / self.minimums.len() as f32).min(1.0);
+
+            self.gain = match target < self.gain {
+                true => target,
+                false => target + (self.gain - target) * self.release,
+            };
+
+            for sample in frame.iter_mut() {
+                self.delay.push_back(*sample);
+                *sample = self.delay.pop_front().unwrap_or(0.0) * self.gain;
+            }
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        let mut settings = self.settings;
+        match name {
+            "ceiling" => settings.ceiling = value,
+            "lookahead" => settings.lookahead = Duration::from_secs_f32(value.max(0.0) / 1000.0),
+            "release" => settings.release = Duration::from_secs_f32(value.max(0.0) / 1000.0),
+            _ => return false,
+        }
+        self.set_settings(settings);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frames: usize, frequency: f32, amplitude: f32) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|frame| {
+                let phase = std::f32::consts::TAU * frequency * frame as f32 / 48000.0;
+                [amplitude * phase.sin(); 2]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limiter_holds_the_ceiling() {
+        let settings = LimiterSettings::default();
+        let mut limiter = Limiter::new(settings);
+        let mut samples = sine(48000, 997.0, 2.0);
+
+        for chunk in samples.chunks_mut(2048) {
+            limiter.process(chunk, 2, 48000);
+        }
+
+        let ceiling = to_gain(settings.ceiling);
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak <= ceiling + 1e-4, "peak {peak} over ceiling {ceiling}");
+        // It limits instead of just turning everything down
+        assert!(
+            peak > ceiling * 0.9,
+            "peak {peak} far under ceiling {ceiling}"
+        );
+    }
+
+    #[test]
+    fn limiter_leaves_quiet_audio_alone() {
+        let mut limiter = Limiter::new(LimiterSettings::default());
+        let input = sine(4800, 440.0, 0.5);
+        let mut samples = input.clone();
+        limiter.process(&mut samples, 2, 48000);
+
+        // Only delayed by the lookahead
+        let delay = limiter.lookahead * 2;
+        for (output, input) in samples[delay..].iter().zip(&input) {
+            assert!((output - input).abs() < 1e-6);
+        }
+    }
+}
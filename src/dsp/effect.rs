@@ -0,0 +1,226 @@
+pub trait AudioEffect: Send {
+    fn name(&self) -> &str;
+
+    // Processes interleaved frames in place
+    fn process(&mut self, samples: &mut [f32], channels: u16, sample_rate: u32);
+
+    // Returns false if the effect has no such parameter
+    fn set_param(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EffectInfo {
+    pub name: String,
+    pub bypassed: bool,
+}
+
+struct Slot {
+    effect: Box<dyn AudioEffect>,
+    bypassed: bool,
+    removed: bool,
+    // Current wet amount, ramped towards 0 or 1 to avoid clicks
+    mix: f32,
+}
+
+impl Slot {
+    fn target(&self) -> f32 {
+        match self.bypassed || self.removed {
+            true => 0.0,
+            false => 1.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct EffectChain {
+    slots: Vec<Slot>,
+    dry: Vec<f32>,
+}
+
+impl EffectChain {
+    pub fn process(&mut self, samples: &mut [f32], channels: u16, sample_rate: u32) {
+        for slot in self.slots.iter_mut() {
+            let target = slot.target();
+
+            if slot.mix == target {
+                if target == 1.0 {
+                    slot.effect.process(samples, channels, sample_rate);
+                }
+                continue;
+            }
+
+            self.dry.clear();
+            self.dry.extend_from_slice(samples);
+            slot.effect.process(samples, channels, sample_rate);
+
+            let frames = samples.len() / channels as usize;
+            let step = (target - slot.mix) / frames.max(1) as f32;
+            for (wet, dry) in samples
+                .chunks_exact_mut(channels as usize)
+                .zip(self.dry.chunks_exact(channels as usize))
+            {
+                for (wet, dry) in wet.iter_mut().zip(dry) {
+                    *wet = *dry + (*wet - *dry) * slot.mix;
+                }
+                slot.mix += step;
+            }
+            slot.mix = target;
+        }
+
+        self.slots.retain(|slot| !slot.removed || slot.mix > 0.0);
+    }
+
+    // Effects being faded out are invisible to the outside
+    fn slot(&mut self, index: usize) -> Option<&mut Slot> {
+        self.slots
+            .iter_mut()
+            .filter(|slot| !slot.removed)
+            .nth(index)
+    }
+
+    fn position(&self, index: usize) -> Option<usize> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| !slot.removed)
+            .nth(index)
+            .map(|(pos, _)| pos)
+    }
+
+    // Effects still fading out count, they are changing the audio
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<EffectInfo> {
+        self.slots
+            .iter()
+            .filter(|slot| !slot.removed)
+            .map(|slot| EffectInfo {
+                name: slot.effect.name().to_string(),
+                bypassed: slot.bypassed,
+            })
+            .collect()
+    }
+
+    pub fn add(&mut self, effect: Box<dyn AudioEffect>) {
+        self.slots.push(Slot {
+            effect,
+            bypassed: false,
+            removed: false,
+            mix: 0.0,
+        });
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if let Some(slot) = self.slot(index) {
+            slot.removed = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.removed = true;
+        }
+    }
+
+    pub fn move_to(&mut self, from: usize, to: usize) {
+        let (Some(from), Some(to)) = (self.position(from), self.position(to)) else {
+            return;
+        };
+
+        let slot = self.slots.remove(from);
+        self.slots.insert(to, slot);
+    }
+
+    pub fn bypass(&mut self, index: usize, bypassed: bool) {
+        if let Some(slot) = self.slot(index) {
+            slot.bypassed = bypassed;
+        }
+    }
+
+    pub fn set_param(&mut self, index: usize, name: &str, value: f32) -> bool {
+        self.slot(index)
+            .is_some_and(|slot| slot.effect.set_param(name, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static str);
+
+    impl AudioEffect for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn process(&mut self, _samples: &mut [f32], _channels: u16, _sample_rate: u32) {}
+
+        fn set_param(&mut self, name: &str, _value: f32) -> bool {
+            name == self.0
+        }
+    }
+
+    fn chain(names: &[&'static str]) -> EffectChain {
+        let mut chain = EffectChain::default();
+        for name in names {
+            chain.add(Box::new(Named(name)));
+        }
+        chain
+    }
+
+    fn names(chain: &EffectChain) -> Vec<String> {
+        chain.list().into_iter().map(|info| info.name).collect()
+    }
+
+    #[test]
+    fn removed_effects_are_skipped_by_indices() {
+        let mut chain = chain(&["a", "b", "c"]);
+        chain.remove(0);
+        assert_eq!(names(&chain), ["b", "c"]);
+
+        // Still fading out, but index 0 is already the next one
+        chain.bypass(0, true);
+        assert!(chain.set_param(1, "c", 1.0));
+        assert!(!chain.set_param(1, "b", 1.0));
+        assert!(chain.list()[0].bypassed);
+        assert!(!chain.is_empty());
+    }
+
+    #[test]
+    fn move_counts_only_visible_effects() {
+        let mut chain = chain(&["a", "b", "c", "d"]);
+        chain.remove(1);
+
+        chain.move_to(0, 2);
+        assert_eq!(names(&chain), ["c", "d", "a"]);
+
+        chain.move_to(2, 0);
+        assert_eq!(names(&chain), ["a", "c", "d"]);
+
+        chain.move_to(0, 1);
+        assert_eq!(names(&chain), ["c", "a", "d"]);
+
+        // Out of range leaves the order alone
+        chain.move_to(0, 3);
+        assert_eq!(names(&chain), ["c", "a", "d"]);
+    }
+
+    #[test]
+    fn removed_effects_go_once_faded_out() {
+        let mut chain = chain(&["a"]);
+        let mut samples = vec![0.0; 256];
+        chain.process(&mut samples, 2, 48000);
+
+        chain.remove(0);
+        assert!(names(&chain).is_empty());
+        assert!(!chain.is_empty());
+
+        chain.process(&mut samples, 2, 48000);
+        assert!(chain.is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+use super::{
+    biquad::{Biquad, Coefficients},
+    AudioEffect,
+};
+
+// ISO octave centers used by the graphic mode
+pub const GRAPHIC_FREQUENCIES: [f32; 10] = [
+    31.25, 62.5, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+const GRAPHIC_Q: f32 = std::f32::consts::SQRT_2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandKind {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+impl BandKind {
+    const ALL: [BandKind; 5] = [
+        BandKind::Peak,
+        BandKind::LowShelf,
+        BandKind::HighShelf,
+        BandKind::LowPass,
+        BandKind::HighPass,
+    ];
+
+    // Used by exported presets
+    fn name(self) -> &'static str {
+        match self {
+            BandKind::Peak => "peak",
+            BandKind::LowShelf => "low_shelf",
+            BandKind::HighShelf => "high_shelf",
+            BandKind::LowPass => "low_pass",
+            BandKind::HighPass => "high_pass",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub kind: BandKind,
+    pub frequency: f32,
+    // Ignored by the pass filters
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl Band {
+    pub fn peak(frequency: f32, gain: f32, q: f32) -> Self {
+        Self {
+            kind: BandKind::Peak,
+            frequency,
+            gain,
+            q,
+        }
+    }
+
+    fn coefficients(&self, sample_rate: u32) -> Coefficients {
+        match self.kind {
+            BandKind::Peak => Coefficients::peaking(sample_rate, self.frequency, self.gain, self.q),
+            BandKind::LowShelf => {
+                Coefficients::low_shelf(sample_rate, self.frequency, self.gain, self.q)
+            }
+            BandKind::HighShelf => {
+                Coefficients::high_shelf(sample_rate, self.frequency, self.gain, self.q)
+            }
+            BandKind::LowPass => Coefficients::low_pass(sample_rate, self.frequency, self.q),
+            BandKind::HighPass => Coefficients::high_pass(sample_rate, self.frequency, self.q),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EqualizerPreset {
+    // Gain in dB applied before the bands
+    pub preamp: f32,
+    pub bands: Vec<Band>,
+}
+
+impl EqualizerPreset {
+    pub fn graphic(gains: [f32; 10]) -> Self {
+        Self {
+            preamp: 0.0,
+            bands: GRAPHIC_FREQUENCIES
+                .iter()
+                .zip(gains)
+                .map(|(frequency, gain)| Band::peak(*frequency, gain, GRAPHIC_Q))
+                .collect(),
+        }
+    }
+
+    // The preamp followed by every band as kind:frequency:gain:q, on one line
+    pub fn to_line(&self) -> String {
+        let mut line = self.preamp.to_string();
+        for band in self.bands.iter() {
+            line += &format!(
+                " {}:{}:{}:{}",
+                band.kind.name(),
+                band.frequency,
+                band.gain,
+                band.q
+            );
+        }
+
+        line
+    }
+
+    pub fn from_line(line: &str) -> Option<Self> {
+        let number = |value: &str| value.parse::<f32>().ok().filter(|value| value.is_finite());
+        let mut parts = line.split_whitespace();
+        let preamp = number(parts.next()?)?;
+
+        let bands = parts
+            .map(|band| {
+                let mut fields = band.split(':');
+                let band = Band {
+                    kind: BandKind::from_name(fields.next()?)?,
+                    frequency: number(fields.next()?)?,
+                    gain: number(fields.next()?)?,
+                    q: number(fields.next()?)?,
+                };
+
+                fields.next().is_none().then_some(band)
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self { preamp, bands })
+    }
+
+    pub fn builtin() -> Vec<(&'static str, EqualizerPreset)> {
+        vec![
+            ("Flat", Self::graphic([0.0; 10])),
+            (
+                "Bass Boost",
+                Self::graphic([6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            ),
+            (
+                "Treble Boost",
+                Self::graphic([0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 4.0, 5.0, 6.0]),
+            ),
+            (
+                "Vocal",
+                Self::graphic([-2.0, -2.0, -1.0, 1.0, 3.0, 3.0, 2.0, 1.0, 0.0, -1.0]),
+            ),
+            (
+                "Loudness",
+                Self::graphic([5.0, 4.0, 2.0, 0.0, -1.0, 0.0, 0.0, 1.0, 3.0, 4.0]),
+            ),
+        ]
+    }
+}
+
+pub struct Equalizer {
+    preset: EqualizerPreset,
+    // One filter per channel for every band
+    filters: Vec<Vec<Biquad>>,
+    sample_rate: u32,
+    dirty: bool,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self::new(EqualizerPreset::graphic([0.0; 10]))
+    }
+}
+
+impl Equalizer {
+    pub fn new(preset: EqualizerPreset) -> Self {
+        Self {
+            preset,
+            filters: Vec::new(),
+            sample_rate: 0,
+            dirty: true,
+        }
+    }
+
+    pub fn preset(&self) -> &EqualizerPreset {
+        &self.preset
+    }
+
+    // Keeps the filter state of bands that still exist so audio doesn't jump
+    pub fn load(&mut self, preset: EqualizerPreset) {
+        self.preset = preset;
+        self.dirty = true;
+    }
+
+    pub fn set_preamp(&mut self, gain: f32) {
+        self.preset.preamp = gain;
+    }
+
+    pub fn set_band(&mut self, index: usize, band: Band) {
+        if let Some(value) = self.preset.bands.get_mut(index) {
+            *value = band;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_gain(&mut self, index: usize, gain: f32) {
+        if let Some(band) = self.preset.bands.get_mut(index) {
+            band.gain = gain;
+            self.dirty = true;
+        }
+    }
+
+    fn update(&mut self, channels: u16, sample_rate: u32) {
+        let channels = channels as usize;
+        self.sample_rate = sample_rate;
+        self.dirty = false;
+
+        self.filters
+            .resize_with(self.preset.bands.len(), Default::default);
+        for (band, filters) in self.preset.bands.iter().zip(self.filters.iter_mut()) {
+            let coefficients = band.coefficients(sample_rate);
+            filters.resize_with(channels, || Biquad::new(Coefficients::IDENTITY));
+            for filter in filters.iter_mut() {
+                filter.set_coefficients(coefficients);
+            }
+        }
+    }
+}
+
+impl AudioEffect for Equalizer {
+    fn name(&self) -> &str {
+        "Equalizer"
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: u16, sample_rate: u32) {
+        if self.dirty
+            || self.sample_rate != sample_rate
+            || self.filters.first().map(Vec::len) != Some(channels as usize)
+        {
+            self.update(channels, sample_rate);
+        }
+
+        let preamp = 10f32.powf(self.preset.preamp / 20.0);
+        for frame in samples.chunks_exact_mut(channels as usize) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = self
+                    .filters
+                    .iter_mut()
+                    .fold(*sample * preamp, |value, filters| {
+                        filters[channel].process(value)
+                    });
+            }
+        }
+    }
+
+    // Parameters are `preamp` and `gain{n}`, `frequency{n}` and `q{n}` per band
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        if name == "preamp" {
+            self.set_preamp(value);
+            return true;
+        }
+
+        let split = name
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(name.len());
+        let (param, index) = name.split_at(split);
+        let Some(band) = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| self.preset.bands.get_mut(index))
+        else {
+            return false;
+        };
+
+        match param {
+            "gain" => band.gain = value,
+            "frequency" => band.frequency = value,
+            "q" => band.q = value,
+            _ => return false,
+        }
+        self.dirty = true;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_lines_round_trip() {
+        let preset = EqualizerPreset {
+            preamp: -3.25,
+            bands: BandKind::ALL
+                .into_iter()
+                .enumerate()
+                .map(|(i, kind)| Band {
+                    kind,
+                    frequency: 31.5 * (i + 1) as f32,
+                    gain: -1.7 + i as f32 * 0.9,
+                    q: 0.707,
+                })
+                .collect(),
+        };
+
+        let line = preset.to_line();
+        assert_eq!(EqualizerPreset::from_line(&line), Some(preset));
+    }
+
+    #[test]
+    fn preset_lines_without_bands() {
+        let preset = EqualizerPreset::from_line("1.5").unwrap();
+        assert_eq!(preset.preamp, 1.5);
+        assert!(preset.bands.is_empty());
+    }
+
+    #[test]
+    fn malformed_preset_lines_are_rejected() {
+        for line in [
+            "",
+            "peak:100:1:1",
+            "0 peak:100:1:1:2",
+            "0 peak:100:1",
+            "0 notch:100:1:1",
+            "0 peak:100:x:1",
+            "NaN peak:100:1:1",
+            "inf peak:100:1:1",
+            "0 peak:inf:1:1",
+            "0 peak:100:-inf:1",
+            "0 peak:100:1:NaN",
+        ] {
+            assert_eq!(EqualizerPreset::from_line(line), None, "{line:?}");
+        }
+    }
+}
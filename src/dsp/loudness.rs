@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use super::biquad::{Biquad, Coefficients};
+use crate::track::SAMPLE_RATE;
+
+// BS.1770 K-weighting at 48kHz: a high shelf followed by a high pass
+const SHELF: Coefficients = Coefficients {
+    b0: 1.535_124_9,
+    b1: -2.691_696_2,
+    b2: 1.198_392_8,
+    a1: -1.690_659_3,
+    a2: 0.732_480_8,
+};
+const HIGH_PASS: Coefficients = Coefficients {
+    b0: 1.0,
+    b1: -2.0,
+    b2: 1.0,
+    a1: -1.990_047_5,
+    a2: 0.990_072_3,
+};
+
+// Gating blocks are 400ms long and overlap by 75%, so they are built out of
+// 100ms sub-blocks
+const SUB_BLOCK_FRAMES: usize = SAMPLE_RATE as usize / 10;
+const BLOCK_SUB_BLOCKS: usize = 4;
+// Short-term loudness uses a 3s window
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    // Integrated loudness in LUFS
+    pub integrated: f32,
+    // Linear sample peak
+    pub peak: f32,
+}
+
+impl Loudness {
+    // ReplayGain 2 references -18 LUFS
+    pub fn from_replay_gain(gain_db: f32, peak: Option<f32>) -> Self {
+        Self {
+            integrated: -18.0 - gain_db,
+            peak: peak.unwrap_or(1.0),
+        }
+    }
+
+    // Combines loudness of several tracks as if they were one album
+    pub fn album(tracks: impl IntoIterator<Item = Loudness>) -> Option<Self> {
+        let mut power = 0.0;
+        let mut peak: f32 = 0.0;
+        let mut count = 0;
+
+        for track in tracks {
+            power += to_power(track.integrated as f64);
+            peak = peak.max(track.peak);
+            count += 1;
+        }
+
+        (count > 0).then(|| Loudness {
+            integrated: to_loudness(power / count as f64) as f32,
+            peak,
+        })
+    }
+}
+
+fn to_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn to_power(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+pub(crate) struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    sum: f64,
+    frames: usize,
+    sub_blocks: VecDeque<f64>,
+    // Gating blocks kept for the integrated loudness, None for live meters
+    blocks: Option<Vec<f64>>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16) -> Self {
+        Self {
+            filters: vec![[Biquad::new(SHELF), Biquad::new(HIGH_PASS)]; channels as usize],
+            sum: 0.0,
+            frames: 0,
+            sub_blocks: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS),
+            blocks: Some(Vec::new()),
+            peak: 0.0,
+        }
+    }
+
+    // Only tracks momentary and short-term loudness, so it can run forever
+    pub fn live(channels: u16) -> Self {
+        Self {
+            blocks: None,
+            ..Self::new(channels)
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        let channels = self.filters.len();
+
+        for frame in samples.chunks_exact(channels) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                self.peak = self.peak.max(sample.abs());
+                let weighted = high_pass.process(shelf.process(*sample)) as f64;
+                self.sum += weighted * weighted;
+            }
+
+            self.frames += 1;
+            if self.frames == SUB_BLOCK_FRAMES {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        if self.sub_blocks.len() == SHORT_TERM_SUB_BLOCKS {
+            self.sub_blocks.pop_front();
+        }
+        self.sub_blocks.push_back(self.sum);
+        self.sum = 0.0;
+        self.frames = 0;
+
+        if let (Some(power), Some(blocks)) = (self.window(BLOCK_SUB_BLOCKS), self.blocks.as_mut()) {
+            blocks.push(power);
+        }
+    }
+
+    // Mean power of the latest `len` sub-blocks
+    fn window(&self, len: usize) -> Option<f64> {
+        (self.sub_blocks.len() >= len).then(|| {
+            self.sub_blocks.iter().rev().take(len).sum::<f64>() / (len * SUB_BLOCK_FRAMES) as f64
+        })
+    }
+
+    // Loudness of the last 400ms in LUFS
+    pub fn momentary(&self) -> Option<f32> {
+        self.window(BLOCK_SUB_BLOCKS)
+            .map(|power| to_loudness(power) as f32)
+    }
+
+    // Loudness of the last 3s in LUFS
+    pub fn short_term(&self) -> Option<f32> {
+        self.window(SHORT_TERM_SUB_BLOCKS)
+            .map(|power| to_loudness(power) as f32)
+    }
+
+    pub fn loudness(&self) -> Option<Loudness> {
+        let blocks = self.blocks.as_ref()?;
+        let gated_mean = |gate: f64| {
+            let (sum, count) = blocks
+                .iter()
+                .filter(|power| to_loudness(**power) > gate)
+                .fold((0.0, 0), |(sum, count), power| (sum + power, count + 1));
+
+            (count > 0).then(|| sum / count as f64)
+        };
+
+        let relative = to_loudness(gated_mean(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+        let integrated = to_loudness(gated_mean(relative.max(ABSOLUTE_GATE))?);
+
+        Some(Loudness {
+            integrated: integrated as f32,
+            peak: self.peak,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalizationMode {
+    Track,
+    Album,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalization {
+    // Target loudness in LUFS
+    pub target: f32,
+    pub mode: NormalizationMode,
+    // Lowers the gain so the track peak never goes over full scale
+    pub prevent_clipping: bool,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            target: -14.0,
+            mode: NormalizationMode::Track,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl Normalization {
+    pub fn gain(&self, loudness: Loudness) -> f32 {
+        let gain = 10f32.powf((self.target - loudness.integrated) / 20.0);
+
+        match self.prevent_clipping && loudness.peak > 0.0 {
+            true => gain.min(1.0 / loudness.peak),
+            false => gain,
+        }
+    }
+}
+
+impl std::fmt::Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Normalization(target: {}, mode: {:?}, prevent_clipping: {})",
+            self.target, self.mode, self.prevent_clipping
+        )
+    }
+}
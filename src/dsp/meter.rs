@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+
+use super::LoudnessMeter;
+
+const FLOOR_DB: f32 = -120.0;
+// Peaks fall back by 20dB per second
+const PEAK_FALL_DB: f32 = 20.0;
+const RMS_WINDOW_SECS: f32 = 0.3;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Levels {
+    // Per channel, in dBFS
+    pub peak: Vec<f32>,
+    pub rms: Vec<f32>,
+    // LUFS, None until enough audio went through
+    pub momentary: Option<f32>,
+    pub short_term: Option<f32>,
+    // Samples that reached or went over full scale
+    pub clips: u64,
+}
+
+fn to_db(value: f32) -> f32 {
+    (20.0 * value.log10()).max(FLOOR_DB)
+}
+
+pub(crate) struct LevelMeter {
+    channels: usize,
+    peak_fall: f32,
+    rms_coefficient: f32,
+    peak: Vec<f32>,
+    mean_square: Vec<f32>,
+    loudness: LoudnessMeter,
+    clips: u64,
+    levels: Arc<Mutex<Levels>>,
+}
+
+impl LevelMeter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+
+        Self {
+            channels: channels as usize,
+            peak_fall: 10f32.powf(-PEAK_FALL_DB / 20.0 / sample_rate),
+            rms_coefficient: (-1.0 / (RMS_WINDOW_SECS * sample_rate)).exp(),
+            peak: vec![0.0; channels as usize],
+            mean_square: vec![0.0; channels as usize],
+            loudness: LoudnessMeter::live(channels),
+            clips: 0,
+            levels: Default::default(),
+        }
+    }
+
+    pub fn levels(&self) -> Arc<Mutex<Levels>> {
+        self.levels.clone()
+    }
+
+    pub fn reset_clips(&mut self) {
+        self.clips = 0;
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for ((sample, peak), mean_square) in frame
+                .iter()
+                .zip(self.peak.iter_mut())
+                .zip(self.mean_square.iter_mut())
+            {
+                let value = sample.abs();
+                if value >= 1.0 {
+                    self.clips += 1;
+                }
+
+                *peak = value.max(*peak * self.peak_fall);
+                *mean_square =
+                    value * value + (*mean_square - value * value) * self.rms_coefficient;
+            }
+        }
+        self.loudness.push(samples);
+
+        // Readers get the previous snapshot rather than stalling the audio
+        if let Ok(mut levels) = self.levels.try_lock() {
+            *levels = Levels {
+                peak: self.peak.iter().copied().map(to_db).collect(),
+                rms: self
+                    .mean_square
+                    .iter()
+                    .map(|value| to_db(value.sqrt()))
+                    .collect(),
+                momentary: self.loudness.momentary(),
+                short_term: self.loudness.short_term(),
+                clips: self.clips,
+            };
+        }
+    }
+}
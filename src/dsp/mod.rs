@@ -0,0 +1,36 @@
+mod biquad;
+
+mod loudness;
+pub(crate) use loudness::LoudnessMeter;
+pub use loudness::{Loudness, Normalization, NormalizationMode};
+
+mod effect;
+pub(crate) use effect::EffectChain;
+pub use effect::{AudioEffect, EffectInfo};
+
+mod equalizer;
+pub use equalizer::{Band, BandKind, Equalizer, EqualizerPreset, GRAPHIC_FREQUENCIES};
+
+mod stretch;
+pub(crate) use stretch::Stretch;
+
+mod resample;
+pub(crate) use resample::Resampler;
+
+mod silence;
+pub(crate) use silence::SilenceDetector;
+pub use silence::{SilenceMode, SkipSilence};
+
+mod channels;
+pub use channels::{ChannelMap, ChannelMapper};
+
+mod spectrum;
+pub use spectrum::SpectrumConfig;
+pub(crate) use spectrum::{SpectrumAnalyzer, SpectrumTap};
+
+mod meter;
+pub(crate) use meter::LevelMeter;
+pub use meter::Levels;
+
+mod dynamics;
+pub use dynamics::{Compressor, CompressorSettings, Limiter, LimiterSettings};
@@ -0,0 +1,93 @@
+use super::biquad::{Biquad, Coefficients};
+
+const PULL_FRAMES: usize = 256;
+// Sections of an 8th order Butterworth low-pass
+const BUTTERWORTH_Q: [f32; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+// Where the anti-aliasing filter cuts, relative to the output rate
+const CUTOFF: f32 = 0.45;
+
+// Linear interpolation resampler reading `ratio` input frames per output frame
+pub(crate) struct Resampler {
+    channels: usize,
+    input: Vec<f32>,
+    position: f64,
+    // Per channel, applied to the input as it is pulled
+    filters: Vec<[Biquad; 4]>,
+}
+
+impl Resampler {
+    pub fn new(channels: u16) -> Self {
+        Self {
+            channels: channels as usize,
+            input: Vec::new(),
+            position: 0.0,
+            filters: Vec::new(),
+        }
+    }
+
+    // For a fixed rate conversion, going down the input is low-passed first
+    // so what the output rate can't hold doesn't fold back into it
+    pub fn with_rates(channels: u16, input_rate: u32, output_rate: u32) -> Self {
+        let mut resampler = Self::new(channels);
+        resampler.set_low_pass(input_rate, input_rate as f64 / output_rate as f64);
+        resampler
+    }
+
+    // Same for a ratio that can change while rendering, above 1 the output
+    // holds less of the input's band, at or below it nothing is filtered
+    pub fn set_low_pass(&mut self, input_rate: u32, ratio: f64) {
+        if ratio <= 1.0 {
+            self.filters.clear();
+            return;
+        }
+
+        let frequency = (input_rate as f64 * CUTOFF as f64 / ratio) as f32;
+        let coefficients = BUTTERWORTH_Q.map(|q| Coefficients::low_pass(input_rate, frequency, q));
+        match self.filters.is_empty() {
+            true => self.filters = vec![coefficients.map(Biquad::new); self.channels],
+            // Kept running so a change doesn't click
+            false => {
+                for filter in self.filters.iter_mut() {
+                    for (section, coefficients) in filter.iter_mut().zip(coefficients) {
+                        section.set_coefficients(coefficients);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn render(&mut self, buf: &mut [f32], ratio: f64, mut fill: impl FnMut(&mut [f32])) {
+        let channels = self.channels;
+
+        for frame in buf.chunks_exact_mut(channels) {
+            let index = self.position as usize;
+            while (index + 1) * channels >= self.input.len() {
+                let len = self.input.len();
+                self.input.resize(len + PULL_FRAMES * channels, 0.0);
+                fill(&mut self.input[len..]);
+
+                if !self.filters.is_empty() {
+                    for frame in self.input[len..].chunks_exact_mut(channels) {
+                        for (sample, filter) in frame.iter_mut().zip(&mut self.filters) {
+                            for section in filter.iter_mut() {
+                                *sample = section.process(*sample);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let t = (self.position - index as f64) as f32;
+            let (a, b) = self.input[index * channels..].split_at(channels);
+            for ((sample, a), b) in frame.iter_mut().zip(a).zip(b) {
+                *sample = a + (b - a) * t;
+            }
+
+            self.position += ratio;
+        }
+
+        let consumed = self.position as usize;
+        self.input.drain(..consumed * channels);
+        self.position -= consumed as f64;
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use crate::track::SAMPLE_RATE;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SilenceMode {
+    // Drops silence past the minimum length entirely
+    Skip,
+    // Plays silence past the minimum length this many times faster
+    Compress(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkipSilence {
+    // Level in dBFS under which a frame counts as silent
+    pub threshold: f32,
+    // Silence shorter than this is left alone
+    pub min_duration: Duration,
+    pub mode: SilenceMode,
+}
+
+impl Default for SkipSilence {
+    fn default() -> Self {
+        Self {
+            threshold: -50.0,
+            min_duration: Duration::from_millis(500),
+            mode: SilenceMode::Skip,
+        }
+    }
+}
+
+impl std::fmt::Display for SkipSilence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SkipSilence(threshold: {}, min_duration: {:?}, mode: {:?})",
+            self.threshold, self.min_duration, self.mode
+        )
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SilenceDetector {
+    silent_frames: u64,
+    // Fraction of a frame owed to the output in compress mode
+    credit: f32,
+}
+
+impl SilenceDetector {
+    // Returns false for frames that should be dropped
+    pub fn keep(&mut self, frame: &[f32], config: &SkipSilence) -> bool {
+        let threshold = 10f32.powf(config.threshold / 20.0);
+        if frame.iter().any(|sample| sample.abs() > threshold) {
+            self.silent_frames = 0;
+            self.credit = 0.0;
+            return true;
+        }
+
+        self.silent_frames += 1;
+        let min_frames = (config.min_duration.as_secs_f64() * SAMPLE_RATE as f64) as u64;
+        if self.silent_frames <= min_frames {
+            return true;
+        }
+
+        match config.mode {
+            SilenceMode::Skip => false,
+            SilenceMode::Compress(factor) => {
+                self.credit += 1.0 / factor.max(1.0);
+                if self.credit >= 1.0 {
+                    self.credit -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SILENT: [f32; 2] = [0.001, -0.001];
+    const LOUD: [f32; 2] = [0.1, 0.0];
+
+    fn config(mode: SilenceMode) -> SkipSilence {
+        SkipSilence {
+            threshold: -50.0,
+            min_duration: Duration::from_millis(10),
+            mode,
+        }
+    }
+
+    // Frames of silence kept before the detector starts dropping them
+    fn kept(detector: &mut SilenceDetector, config: &SkipSilence, frames: usize) -> usize {
+        (0..frames)
+            .filter(|_| detector.keep(&SILENT, config))
+            .count()
+    }
+
+    #[test]
+    fn loudest_channel_is_compared_to_the_threshold() {
+        let config = config(SilenceMode::Skip);
+
+        // -50 dBFS is about 0.00316
+        for (sample, expected) in [(0.003, 480), (-0.003, 480), (0.0033, 1000), (-0.0033, 1000)] {
+            let mut detector = SilenceDetector::default();
+            let kept = (0..1000)
+                .filter(|_| detector.keep(&[0.0, sample], &config))
+                .count();
+            assert_eq!(kept, expected, "sample {sample}");
+        }
+    }
+
+    #[test]
+    fn short_silence_is_kept() {
+        let mut detector = SilenceDetector::default();
+        let config = config(SilenceMode::Skip);
+
+        assert_eq!(kept(&mut detector, &config, 480), 480);
+        assert!(detector.keep(&LOUD, &config));
+        // A loud frame starts the count over
+        assert_eq!(kept(&mut detector, &config, 480), 480);
+        assert!(!detector.keep(&SILENT, &config));
+    }
+
+    #[test]
+    fn compress_keeps_a_fraction() {
+        let mut detector = SilenceDetector::default();
+        let config = config(SilenceMode::Compress(4.0));
+
+        assert_eq!(kept(&mut detector, &config, 480 + 4000), 480 + 1000);
+    }
+}
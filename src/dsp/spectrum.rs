@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+const FFT_SIZE: usize = 2048;
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20000.0;
+const FLOOR_DB: f32 = -120.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectrumConfig {
+    // Number of logarithmically spaced bands between 20Hz and 20kHz
+    pub bands: usize,
+    // 0.0 follows the signal exactly, values close to 1.0 decay slowly
+    pub smoothing: f32,
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        Self {
+            bands: 32,
+            smoothing: 0.7,
+        }
+    }
+}
+
+impl std::fmt::Display for SpectrumConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SpectrumConfig(bands: {}, smoothing: {})",
+            self.bands, self.smoothing
+        )
+    }
+}
+
+struct Ring {
+    samples: Vec<f32>,
+    pos: usize,
+}
+
+// Audio thread side, only ever uses try_lock so it can't be held up by readers
+#[derive(Clone)]
+pub(crate) struct SpectrumTap {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl Default for SpectrumTap {
+    fn default() -> Self {
+        Self {
+            ring: Arc::new(Mutex::new(Ring {
+                samples: vec![0.0; FFT_SIZE],
+                pos: 0,
+            })),
+        }
+    }
+}
+
+impl SpectrumTap {
+    pub fn push(&self, samples: &[f32], channels: u16) {
+        let Ok(mut ring) = self.ring.try_lock() else {
+            return;
+        };
+
+        for frame in samples.chunks_exact(channels as usize) {
+            let pos = ring.pos;
+            ring.samples[pos] = frame.iter().sum::<f32>() / channels as f32;
+            ring.pos = (pos + 1) % FFT_SIZE;
+        }
+    }
+}
+
+pub(crate) struct SpectrumAnalyzer {
+    tap: SpectrumTap,
+    config: SpectrumConfig,
+    sample_rate: u32,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buf: Vec<Complex<f32>>,
+    smoothed: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(tap: SpectrumTap, sample_rate: u32) -> Self {
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        Self {
+            tap,
+            config: Default::default(),
+            sample_rate,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            buf: vec![Complex::default(); FFT_SIZE],
+            smoothed: Vec::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: SpectrumConfig) {
+        self.config = config;
+        self.smoothed.clear();
+    }
+
+    // Band levels in dBFS, lowest band first
+    pub fn spectrum(&mut self) -> Vec<f32> {
+        {
+            let ring = self.tap.ring.lock().unwrap();
+            let (newer, older) = ring.samples.split_at(ring.pos);
+            for ((value, sample), weight) in self
+                .buf
+                .iter_mut()
+                .zip(older.iter().chain(newer))
+                .zip(self.window.iter())
+            {
+                *value = Complex::new(sample * weight, 0.0);
+            }
+        }
+
+        self.fft.process(&mut self.buf);
+
+        // Scale so a full scale sine reads 0dB
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+        let bin_width = self.sample_rate as f32 / FFT_SIZE as f32;
+        let bands = self.config.bands.max(1);
+        let ratio = MAX_FREQUENCY / MIN_FREQUENCY;
+
+        self.smoothed.resize(bands, 0.0);
+        for (band, smoothed) in self.smoothed.iter_mut().enumerate() {
+            let low = MIN_FREQUENCY * ratio.powf(band as f32 / bands as f32);
+            let high = MIN_FREQUENCY * ratio.powf((band + 1) as f32 / bands as f32);
+            let low = ((low / bin_width) as usize).clamp(1, FFT_SIZE / 2 - 1);
+            let high = ((high / bin_width) as usize).clamp(low + 1, FFT_SIZE / 2);
+
+            let magnitude = self.buf[low..high]
+                .iter()
+                .map(|value| value.norm() * scale)
+                .fold(0.0, f32::max);
+
+            let smoothing = self.config.smoothing.clamp(0.0, 0.99);
+            *smoothed = *smoothed * smoothing + magnitude * (1.0 - smoothing);
+        }
+
+        self.smoothed
+            .iter()
+            .map(|value| (20.0 * value.log10()).max(FLOOR_DB))
+            .collect()
+    }
+}
@@ -0,0 +1,140 @@
+// WSOLA time stretching: windows are taken from the input at `speed` times the
+// output hop, each shifted within a small tolerance to the offset that best
+// continues the previous window, and overlap-added at a fixed hop so the pitch
+// is left untouched
+const WINDOW: usize = 1024;
+const HOP: usize = WINDOW / 2;
+const TOLERANCE: u64 = 256;
+// Only every n-th frame is used when comparing windows
+const CORRELATION_STEP: usize = 4;
+
+pub(crate) struct Stretch {
+    channels: usize,
+    window: Vec<f32>,
+    // Interleaved input with `offset` being the index of its first frame
+    input: Vec<f32>,
+    offset: u64,
+    position: f64,
+    previous: Option<u64>,
+    output: Vec<f32>,
+    ready: Vec<f32>,
+    ready_pos: usize,
+}
+
+impl Stretch {
+    pub fn new(channels: u16) -> Self {
+        let channels = channels as usize;
+        let window = (0..WINDOW)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / WINDOW as f32).cos())
+            .collect();
+
+        Self {
+            channels,
+            window,
+            input: Vec::new(),
+            offset: 0,
+            position: 0.0,
+            previous: None,
+            output: vec![0.0; WINDOW * channels],
+            ready: Vec::with_capacity(HOP * channels),
+            ready_pos: 0,
+        }
+    }
+
+    pub fn render(&mut self, buf: &mut [f32], speed: f64, mut fill: impl FnMut(&mut [f32])) {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.ready_pos >= self.ready.len() {
+                self.step(speed, &mut fill);
+            }
+
+            let len = (buf.len() - written).min(self.ready.len() - self.ready_pos);
+            buf[written..written + len]
+                .copy_from_slice(&self.ready[self.ready_pos..self.ready_pos + len]);
+            written += len;
+            self.ready_pos += len;
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.offset + (self.input.len() / self.channels) as u64
+    }
+
+    fn frame(&self, index: u64) -> &[f32] {
+        let start = (index - self.offset) as usize * self.channels;
+        &self.input[start..start + self.channels]
+    }
+
+    fn mono(&self, index: u64) -> f32 {
+        self.frame(index).iter().sum()
+    }
+
+    fn step(&mut self, speed: f64, fill: &mut impl FnMut(&mut [f32])) {
+        let nominal = self.position.round() as u64;
+        let search = nominal.saturating_sub(TOLERANCE).max(self.offset)..=nominal + TOLERANCE;
+
+        let needed = *search.end() + WINDOW as u64;
+        while self.end() < needed {
+            let len = self.input.len();
+            self.input.resize(len + HOP * self.channels, 0.0);
+            fill(&mut self.input[len..]);
+        }
+
+        let chosen = match self.previous {
+            Some(previous) => {
+                let natural = previous + HOP as u64;
+                let mut best = (f32::MIN, nominal);
+
+                for candidate in search {
+                    let (mut correlation, mut energy) = (0.0, 0.0);
+                    for i in (0..HOP as u64).step_by(CORRELATION_STEP) {
+                        let value = self.mono(candidate + i);
+                        correlation += value * self.mono(natural + i);
+                        energy += value * value;
+                    }
+
+                    let score = correlation / energy.sqrt().max(f32::EPSILON);
+                    if score > best.0 {
+                        best = (score, candidate);
+                    }
+                }
+
+                best.1
+            }
+            None => nominal,
+        };
+
+        let start = (chosen - self.offset) as usize * self.channels;
+        let input = &self.input[start..start + WINDOW * self.channels];
+        for ((output, input), weight) in self
+            .output
+            .chunks_exact_mut(self.channels)
+            .zip(input.chunks_exact(self.channels))
+            .zip(self.window.iter())
+        {
+            for (output, input) in output.iter_mut().zip(input) {
+                *output += input * weight;
+            }
+        }
+
+        let hop = HOP * self.channels;
+        self.ready.clear();
+        self.ready.extend_from_slice(&self.output[..hop]);
+        self.ready_pos = 0;
+        self.output.copy_within(hop.., 0);
+        self.output[WINDOW * self.channels - hop..].fill(0.0);
+
+        self.previous = Some(chosen);
+        self.position += HOP as f64 * speed;
+
+        // Drop input neither the next search nor the next comparison can reach
+        let keep = (chosen + HOP as u64).min(
+            (self.position.round() as u64)
+                .saturating_sub(TOLERANCE)
+                .max(self.offset),
+        );
+        let drop = (keep - self.offset) as usize * self.channels;
+        self.input.drain(..drop);
+        self.offset = keep;
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use flacenc::{
+    bitsink::ByteSink,
+    component::{BitRepr, MetadataBlockData, Stream},
+    config,
+    error::{Verified, Verify},
+    source::{Context, Fill, FrameBuf},
+};
+
+use crate::track::{CHANNELS, SAMPLE_RATE};
+
+// Recommended by libopus for a single packet
+pub(crate) const MAX_PACKET: usize = 4000;
+
+// Stereo at the mixer's rate, bitrate in kbps clamped to what Opus takes
+pub(crate) fn opus_encoder(bitrate: u32) -> Result<opus::Encoder, opus::Error> {
+    let mut encoder = opus::Encoder::new(
+        SAMPLE_RATE,
+        opus::Channels::Stereo,
+        opus::Application::Audio,
+    )?;
+    encoder.set_bitrate(opus::Bitrate::Bits(bitrate.clamp(6, 510) as i32 * 1000))?;
+    Ok(encoder)
+}
+
+// FLAC written a block at a time instead of encoded in memory. STREAMINFO has
+// a fixed size, so the header is rewritten in place whenever it is flushed and
+// the file is valid at that point
+pub(crate) struct FlacWriter {
+    file: BufWriter<File>,
+    config: Verified<config::Encoder>,
+    // Only the metadata, frames are written out as they are encoded
+    stream: Stream,
+    fill: (FrameBuf, Context),
+    scale: f32,
+    samples: Vec<i32>,
+    sink: ByteSink,
+}
+
+impl FlacWriter {
+    // `metadata` are extra blocks as (type, data)
+    pub fn create(
+        path: &Path,
+        bits: usize,
+        block_frames: usize,
+        metadata: Vec<(u8, Vec<u8>)>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = config::Encoder::default()
+            .into_verified()
+            .map_err(|(_, e)| e)?;
+        let mut stream = Stream::new(SAMPLE_RATE as usize, CHANNELS as usize, bits)?;
+        stream
+            .stream_info_mut()
+            .set_block_sizes(block_frames, block_frames)?;
+        for (kind, data) in metadata {
+            stream.add_metadata_block(MetadataBlockData::new_unknown(kind, &data)?);
+        }
+
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            config,
+            stream,
+            fill: (
+                FrameBuf::with_size(CHANNELS as usize, block_frames)?,
+                Context::new(bits, CHANNELS as usize),
+            ),
+            scale: (1 << (bits - 1)) as f32,
+            samples: Vec::with_capacity(block_frames * CHANNELS as usize),
+            sink: ByteSink::new(),
+        };
+        writer.stream.write(&mut writer.sink)?;
+        writer.file.write_all(writer.sink.as_slice())?;
+
+        Ok(writer)
+    }
+
+    // One block of interleaved samples, only the last one may be shorter
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+        let scale = self.scale;
+        self.samples.clear();
+        self.samples.extend(
+            samples
+                .iter()
+                .map(|sample| (sample * scale).clamp(-scale, scale - 1.0) as i32),
+        );
+        self.fill.fill_interleaved(&self.samples)?;
+
+        let frame = flacenc::encode_fixed_size_frame(
+            &self.config,
+            &self.fill.0,
+            self.fill.1.current_frame_number().unwrap_or_default(),
+            self.stream.stream_info(),
+        )?;
+        self.stream.stream_info_mut().update_frame_info(&frame);
+
+        self.sink.clear();
+        frame.write(&mut self.sink)?;
+        Ok(self.file.write_all(self.sink.as_slice())?)
+    }
+
+    // Brings the header up to date with the checksum of everything written
+    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let md5 = self.fill.1.md5_digest();
+        self.stream.stream_info_mut().set_md5_digest(&md5);
+
+        self.sink.clear();
+        self.stream.write(&mut self.sink)?;
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(self.sink.as_slice())?;
+        self.file.seek(SeekFrom::End(0))?;
+
+        Ok(self.file.flush()?)
+    }
+}
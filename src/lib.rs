@@ -1,5 +1,21 @@
+mod dsp;
+pub use dsp::{
+    AudioEffect, Band, BandKind, ChannelMap, ChannelMapper, Compressor, CompressorSettings,
+    EffectInfo, Equalizer, EqualizerPreset, Levels, Limiter, LimiterSettings, Loudness,
+    Normalization, NormalizationMode, SilenceMode, SkipSilence, SpectrumConfig,
+    GRAPHIC_FREQUENCIES,
+};
+
+mod encode;
+
 mod track;
-pub use track::{Track, TrackSourceHandle, TracksQueue, TracksQueueHandle};
+pub use track::{
+    Codec, DownloadEvent, Track, TrackSourceHandle, TracksQueue, TracksQueueHandle, Waveform,
+};
 
 mod manager;
-pub use manager::{AudioManager, Play, Queue, Request};
+pub use manager::{
+    AudioManager, AudioSink, Channels, Crossfade, CustomSink, Effects, Equalization, FadeCurve,
+    FileFormat, OpusFrame, OpusSender, Output, OutputDevice, Play, Queue, Request, SampleFormat,
+    Samples, Seek, SinkFormat,
+};
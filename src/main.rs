@@ -6,6 +6,12 @@ fn main() {
         .build()
         .unwrap();
     let rt = Arc::new(rt);
+
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "transcode") {
+        return transcode(&rt, &args[1..]);
+    }
+
     let manager = audio::AudioManager::new(rt.clone());
 
     manager.send(audio::Request::Queue(audio::Queue::Add(
@@ -26,3 +32,58 @@ fn main() {
         println!("{}/{}", track.current_time(), track.metadata().duration);
     }
 }
+
+// transcode <opus[:kbps]|mp3[:kbps]|flac> <dir> <id>...
+fn transcode(rt: &tokio::runtime::Runtime, args: &[String]) {
+    let (Some(codec), Some(dir)) = (args.first().and_then(|arg| codec(arg)), args.get(1)) else {
+        eprintln!("usage: transcode <opus[:kbps]|mp3[:kbps]|flac> <dir> <id>...");
+        std::process::exit(2);
+    };
+
+    let mut failed = false;
+    for id in &args[2..] {
+        let done = rt.block_on(async {
+            let track = audio::Track::new(id).await?;
+            let path =
+                std::path::Path::new(dir).join(format!("{}.{}", track.id, codec.extension()));
+            let title = track.title.clone();
+
+            track
+                .transcode(&path, codec, move |value| {
+                    println!("{title}: {:.0}%", value * 100.0)
+                })
+                .await?;
+            Some(path)
+        });
+
+        match done {
+            Some(path) => println!("{id}: {}", path.display()),
+            None => {
+                eprintln!("{id}: failed");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn codec(arg: &str) -> Option<audio::Codec> {
+    let (name, bitrate) = match arg.split_once(':') {
+        Some((name, bitrate)) => (name, Some(bitrate.parse().ok()?)),
+        None => (arg, None),
+    };
+
+    match name {
+        "opus" => Some(audio::Codec::Opus {
+            bitrate: bitrate.unwrap_or(128),
+        }),
+        "mp3" => Some(audio::Codec::Mp3 {
+            bitrate: bitrate.unwrap_or(192),
+        }),
+        "flac" => Some(audio::Codec::Flac),
+        _ => None,
+    }
+}
@@ -0,0 +1,808 @@
+use std::{
+    sync::{atomic, mpsc, Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+use crate::{
+    dsp::{
+        AudioEffect, ChannelMapper, Compressor, CompressorSettings, EffectChain, Equalizer,
+        LevelMeter, Levels, Limiter, LimiterSettings, Resampler, SilenceDetector, SkipSilence,
+        SpectrumTap, Stretch,
+    },
+    track::{Packet, TrackSource, CHANNELS, PACKET_FRAMES, SAMPLE_RATE},
+};
+
+// Rendered at a time, also what outputs pull between checks that the decoders
+// are ready
+pub(crate) const CHUNK_FRAMES: usize = 1024;
+// Fades around pause, resume, seek and skip
+const DEFAULT_FADE: Duration = Duration::from_millis(30);
+// Silence dropped per chunk at most, so a long one can't stall the callback
+const MAX_SKIPPED_FRAMES: usize = SAMPLE_RATE as usize / 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FadeCurve {
+    Linear,
+    EqualPower,
+}
+
+impl FadeCurve {
+    // Returns (outgoing, incoming) gains for progress `t` in 0..=1
+    fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => (1.0 - t, t),
+            FadeCurve::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for FadeCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            FadeCurve::Linear => write!(f, "Linear"),
+            FadeCurve::EqualPower => write!(f, "EqualPower"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crossfade {
+    pub duration: Duration,
+    pub curve: FadeCurve,
+}
+
+impl std::fmt::Display for Crossfade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Crossfade(duration: {:?}, curve: {})",
+            self.duration, self.curve
+        )
+    }
+}
+
+struct Deck {
+    source: TrackSource,
+    signal: Option<mpsc::Sender<()>>,
+    gain: f32,
+    gain_step: f32,
+    silence: SilenceDetector,
+    // Frames dropped as silence in this chunk
+    skipped: usize,
+}
+
+impl Deck {
+    fn new(source: TrackSource) -> (Self, mpsc::Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        let deck = Deck {
+            gain: source.gain(),
+            gain_step: 0.0,
+            silence: Default::default(),
+            skipped: 0,
+            source,
+            signal: Some(tx),
+        };
+
+        (deck, rx)
+    }
+
+    // Ramps towards the source gain over the next `frames` frames
+    fn begin_chunk(&mut self, frames: usize) {
+        self.gain_step = (self.source.gain() - self.gain) / frames as f32;
+        self.skipped = 0;
+    }
+
+    // Fills the frame and returns false once the source is exhausted
+    //
+    // Skipped silence is simply not passed on, the source keeps reporting the
+    // timestamp of what it decoded so the track time stays the media time
+    fn next_frame(&mut self, frame: &mut [f32], skip_silence: Option<&SkipSilence>) -> bool {
+        loop {
+            for sample in frame.iter_mut() {
+                match self.source.next() {
+                    Some(value) => *sample = value,
+                    None => return false,
+                }
+            }
+
+            // Zeros from a starved source aren't the track's silence
+            let skippable = !self.source.is_starved() && self.skipped < MAX_SKIPPED_FRAMES;
+            match skip_silence {
+                Some(config) if skippable && !self.silence.keep(frame, config) => self.skipped += 1,
+                _ => break,
+            }
+        }
+
+        for sample in frame.iter_mut() {
+            *sample *= self.gain;
+        }
+        self.gain += self.gain_step;
+
+        true
+    }
+
+    fn finish(&mut self) {
+        if let Some(signal) = self.signal.take() {
+            _ = signal.send(());
+        }
+    }
+}
+
+struct Transition {
+    from: Deck,
+    curve: FadeCurve,
+    pos: u64,
+    len: u64,
+}
+
+// Master gain ramp that holds the decks still while faded out
+struct Fader {
+    gain: f32,
+    target: f32,
+    duration: Duration,
+    // Woken once the gain reaches the target
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+impl Default for Fader {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            target: 1.0,
+            duration: DEFAULT_FADE,
+            waiters: Vec::new(),
+        }
+    }
+}
+
+impl Fader {
+    fn is_silent(&self) -> bool {
+        self.gain == 0.0 && self.target == 0.0
+    }
+
+    fn process(&mut self, buf: &mut [f32]) {
+        let step = match self.duration.as_secs_f32() * SAMPLE_RATE as f32 {
+            frames if frames >= 1.0 => 1.0 / frames,
+            _ => 1.0,
+        };
+
+        for frame in buf.chunks_exact_mut(CHANNELS as usize) {
+            self.gain = match self.gain < self.target {
+                true => (self.gain + step).min(self.target),
+                false => (self.gain - step).max(self.target),
+            };
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+
+        if self.gain == self.target {
+            for waiter in self.waiters.drain(..) {
+                _ = waiter.send(());
+            }
+        }
+    }
+}
+
+struct State {
+    current: Option<Deck>,
+    next: Option<Deck>,
+    outgoing: Option<Transition>,
+    crossfade: Option<Crossfade>,
+    skip_crossfade: Option<Crossfade>,
+    effects: EffectChain,
+    equalizer: Equalizer,
+    equalizer_enabled: bool,
+    speed: f32,
+    // Semitones
+    pitch: f32,
+    // Stay active once used so returning to neutral doesn't drop buffered audio
+    stretch: Option<Stretch>,
+    resampler: Option<Resampler>,
+    skip_silence: Option<SkipSilence>,
+    channels: ChannelMapper,
+    compressor: Option<Compressor>,
+    volume: f32,
+    // Volume actually applied, ramped towards `volume` every chunk
+    volume_gain: f32,
+    limiter: Option<Limiter>,
+    fader: Fader,
+    // Decks wait for their decoder, set for outputs that aren't real time
+    blocking: bool,
+    // Decks hand over their Opus packets, set for Opus outputs
+    passthrough: bool,
+    // The queue has a track to follow the current one that isn't appended
+    // yet, wanted this long before the current one ends
+    awaiting_next: Option<Duration>,
+    spectrum: SpectrumTap,
+    meter: LevelMeter,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            current: None,
+            next: None,
+            outgoing: None,
+            crossfade: None,
+            skip_crossfade: None,
+            effects: Default::default(),
+            equalizer: Default::default(),
+            equalizer_enabled: false,
+            speed: 1.0,
+            pitch: 0.0,
+            stretch: None,
+            resampler: None,
+            skip_silence: None,
+            channels: Default::default(),
+            compressor: None,
+            volume: 1.0,
+            volume_gain: 1.0,
+            limiter: None,
+            fader: Default::default(),
+            blocking: false,
+            passthrough: false,
+            awaiting_next: None,
+            spectrum: Default::default(),
+            meter: LevelMeter::new(CHANNELS, SAMPLE_RATE),
+        }
+    }
+}
+
+impl State {
+    // Crossfades are mixed before stretching, so they are scaled by the speed
+    // to keep their length in real time
+    fn media_duration(&self, duration: Duration) -> Duration {
+        duration.mul_f32(self.speed)
+    }
+
+    fn decks_mut(&mut self) -> impl Iterator<Item = &mut Deck> {
+        let outgoing = self
+            .outgoing
+            .as_mut()
+            .map(|transition| &mut transition.from);
+
+        [self.current.as_mut(), self.next.as_mut(), outgoing]
+            .into_iter()
+            .flatten()
+    }
+
+    // The current track is within `lead` and the crossfade of its end with
+    // nothing appended after it
+    fn wants_next(&self, lead: Duration) -> bool {
+        let crossfade = self
+            .crossfade
+            .map_or(Duration::ZERO, |crossfade| crossfade.duration);
+        // Track times are media time, which passes faster when sped up
+        let lead = self.media_duration(lead + crossfade);
+
+        self.next.is_none()
+            && self.current.as_ref().is_some_and(|deck| {
+                deck.source
+                    .remaining()
+                    .is_some_and(|remaining| remaining <= lead)
+            })
+    }
+
+    // Every deck that could be pulled has a chunk's worth decoded, so a
+    // blocking render won't wait on a decoder while holding the lock. Also
+    // waits for the next track, an offline output would otherwise get to the
+    // end before the poll loop preloaded it
+    fn is_ready(&mut self) -> bool {
+        if self.awaiting_next.is_some_and(|lead| self.wants_next(lead)) {
+            return false;
+        }
+
+        let mut frames = CHUNK_FRAMES as f32 * 2.0 * self.speed.max(1.0);
+        if self.skip_silence.is_some() {
+            frames += MAX_SKIPPED_FRAMES as f32;
+        }
+        let samples = frames as usize * CHANNELS as usize;
+
+        self.decks_mut().all(|deck| deck.source.is_ready(samples))
+    }
+
+    // Only the current track is playing and nothing touches its samples
+    fn is_transparent(&self) -> bool {
+        let deck = self
+            .current
+            .as_ref()
+            .is_some_and(|deck| deck.gain == 1.0 && deck.source.gain() == 1.0);
+        let fader = self.fader.gain == 1.0 && self.fader.target == 1.0;
+        let volume = self.volume == 1.0 && self.volume_gain == 1.0;
+
+        deck && fader
+            && volume
+            && self.outgoing.is_none()
+            && self.stretch.is_none()
+            && self.resampler.is_none()
+            && self.skip_silence.is_none()
+            && !self.equalizer_enabled
+            && self.effects.is_empty()
+            && self.channels.is_identity()
+            && self.compressor.is_none()
+            && self.limiter.is_none()
+    }
+
+    // Renders `buf` and returns the current track's packet for it if the
+    // audio is exactly what that packet decodes to. Also returns the frame of
+    // the track the chunk started at
+    fn render_passthrough(&mut self, buf: &mut [f32]) -> (Option<u64>, Option<Box<[u8]>>) {
+        let transparent = self.is_transparent();
+        let start = self.current.as_ref().map(|deck| deck.source.frame());
+        self.render(buf);
+
+        let transparent = transparent && self.is_transparent();
+        let Some(current) = self.current.as_mut() else {
+            return (start, None);
+        };
+
+        // Everything read is taken so nothing stale is left over for later
+        let end = current.source.frame();
+        let mut packets = std::iter::from_fn(|| current.source.pop_packet(end));
+        let (packet, extra) = (packets.next(), packets.next());
+        packets.for_each(drop);
+
+        // One packet lined up exactly with the chunk. A track change, a seek
+        // or a track starting partway through a chunk leaves them offset,
+        // those chunks are re-encoded
+        let aligned = |packet: &Packet| {
+            start == Some(packet.start) && packet.start + PACKET_FRAMES as u64 == end
+        };
+        match (packet, extra) {
+            (Some(packet), None) if transparent && aligned(&packet) => (start, Some(packet.data)),
+            _ => (start, None),
+        }
+    }
+
+    fn start_transition(&mut self, crossfade: Crossfade) {
+        let Some(mut from) = self.current.take() else {
+            return;
+        };
+
+        from.finish();
+        self.outgoing = Some(Transition {
+            from,
+            curve: crossfade.curve,
+            pos: 0,
+            len: (self.media_duration(crossfade.duration).as_secs_f64() * SAMPLE_RATE as f64)
+                as u64,
+        });
+    }
+
+    fn render(&mut self, buf: &mut [f32]) {
+        // Paused, nothing gets pulled from the decks so they stay in place
+        if self.fader.is_silent() {
+            self.fader.process(buf);
+            buf.fill(0.0);
+            self.spectrum.push(buf, CHANNELS);
+            self.meter.push(buf);
+            return;
+        }
+
+        let speed = self.speed as f64;
+        let ratio = 2f64.powf(self.pitch as f64 / 12.0);
+
+        // Pitch is shifted by resampling, which also changes the tempo, so the
+        // stretch makes up for it
+        match (self.stretch.take(), self.resampler.take()) {
+            (Some(mut stretch), Some(mut resampler)) => {
+                resampler.render(buf, ratio, |input| {
+                    stretch.render(input, speed / ratio, |input| self.mix(input))
+                });
+                self.stretch = Some(stretch);
+                self.resampler = Some(resampler);
+            }
+            (Some(mut stretch), None) => {
+                stretch.render(buf, speed, |input| self.mix(input));
+                self.stretch = Some(stretch);
+            }
+            _ => self.mix(buf),
+        }
+
+        if self.equalizer_enabled {
+            self.equalizer.process(buf, CHANNELS, SAMPLE_RATE);
+        }
+        self.effects.process(buf, CHANNELS, SAMPLE_RATE);
+        self.channels.process(buf, CHANNELS, SAMPLE_RATE);
+        if let Some(ref mut compressor) = self.compressor {
+            compressor.process(buf, CHANNELS, SAMPLE_RATE);
+        }
+        self.apply_volume(buf);
+        // Last so nothing can push the output over the ceiling
+        if let Some(ref mut limiter) = self.limiter {
+            limiter.process(buf, CHANNELS, SAMPLE_RATE);
+        }
+        self.fader.process(buf);
+        self.spectrum.push(buf, CHANNELS);
+        self.meter.push(buf);
+    }
+
+    fn apply_volume(&mut self, buf: &mut [f32]) {
+        let frames = buf.len() / CHANNELS as usize;
+        let step = (self.volume - self.volume_gain) / frames as f32;
+
+        for frame in buf.chunks_exact_mut(CHANNELS as usize) {
+            for sample in frame.iter_mut() {
+                *sample *= self.volume_gain;
+            }
+            self.volume_gain += step;
+        }
+        self.volume_gain = self.volume;
+    }
+
+    fn mix(&mut self, buf: &mut [f32]) {
+        if let (Some(crossfade), None, Some(current)) =
+            (self.crossfade, &self.outgoing, &self.current)
+        {
+            let duration = self.media_duration(crossfade.duration);
+            let due = current
+                .source
+                .remaining()
+                .is_some_and(|remaining| remaining <= duration);
+
+            if due && self.next.is_some() {
+                self.start_transition(crossfade);
+                self.current = self.next.take();
+            }
+        }
+
+        let frames = buf.len() / CHANNELS as usize;
+        if let Some(ref mut current) = self.current {
+            current.begin_chunk(frames);
+        }
+        if let Some(ref mut transition) = self.outgoing {
+            transition.from.begin_chunk(frames);
+        }
+
+        let skip_silence = self.skip_silence.as_ref();
+        let mut other = [0.0; CHANNELS as usize];
+        for frame in buf.chunks_exact_mut(CHANNELS as usize) {
+            frame.fill(0.0);
+
+            while let Some(ref mut current) = self.current {
+                if current.next_frame(frame, skip_silence) {
+                    break;
+                }
+
+                current.finish();
+                frame.fill(0.0);
+                self.current = self.next.take();
+            }
+
+            let Some(ref mut transition) = self.outgoing else {
+                continue;
+            };
+
+            let (out_gain, in_gain) = transition
+                .curve
+                .gains(transition.pos as f32 / transition.len.max(1) as f32);
+            let alive = transition.from.next_frame(&mut other, skip_silence);
+
+            for (sample, other) in frame.iter_mut().zip(other.iter()) {
+                *sample = *sample * in_gain + if alive { *other * out_gain } else { 0.0 };
+            }
+
+            transition.pos += 1;
+            if !alive || transition.pos >= transition.len {
+                self.outgoing = None;
+            }
+        }
+    }
+}
+
+pub(crate) struct Mixer {
+    state: Arc<Mutex<State>>,
+    rendered: Arc<atomic::AtomicU64>,
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl Mixer {
+    // For Opus outputs, which render 20ms at a time instead of iterating.
+    // Returns where in the track the frame starts and the original packet
+    // when it can be sent as is
+    pub fn render_packet(&mut self, buf: &mut [f32]) -> (Option<Duration>, Option<Box<[u8]>>) {
+        debug_assert_eq!(buf.len(), PACKET_FRAMES * CHANNELS as usize);
+        let (start, packet) = self.state.lock().unwrap().render_passthrough(buf);
+
+        let position =
+            start.map(|frame| Duration::from_secs_f64(frame as f64 / SAMPLE_RATE as f64));
+        (position, packet)
+    }
+}
+
+impl Iterator for Mixer {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.buf.len() {
+            self.state.lock().unwrap().render(&mut self.buf);
+            self.rendered.fetch_add(1, atomic::Ordering::Relaxed);
+            self.pos = 0;
+        }
+
+        let sample = self.buf[self.pos];
+        self.pos += 1;
+
+        Some(sample)
+    }
+}
+
+impl rodio::Source for Mixer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct MixerHandle {
+    state: Arc<Mutex<State>>,
+    // Chunks pulled through the iterator, read without the state lock
+    rendered: Arc<atomic::AtomicU64>,
+    // Told when an offline output has written everything it was given
+    finished: Arc<Mutex<Vec<mpsc::Sender<()>>>>,
+}
+
+impl MixerHandle {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            rendered: Default::default(),
+            finished: Default::default(),
+        }
+    }
+
+    // Renders from the shared state, so only one may be playing at a time
+    pub fn source(&self) -> Mixer {
+        Mixer {
+            state: self.state.clone(),
+            rendered: self.rendered.clone(),
+            buf: vec![0.0; CHUNK_FRAMES * CHANNELS as usize],
+            pos: CHUNK_FRAMES * CHANNELS as usize,
+        }
+    }
+
+    // Replaces whatever is playing, fading it out if a skip crossfade is set
+    pub fn play(&self, mut source: TrackSource) -> mpsc::Receiver<()> {
+        let state = &mut *self.state.lock().unwrap();
+        source.set_blocking(state.blocking);
+        source.set_passthrough(state.passthrough);
+        let (deck, signal) = Deck::new(source);
+
+        // The replaced track was skipped, not finished, so don't signal it
+        if let Some(ref mut current) = state.current {
+            current.signal = None;
+        }
+
+        match state.skip_crossfade {
+            Some(crossfade) => state.start_transition(crossfade),
+            None => state.outgoing = None,
+        }
+
+        state.current = Some(deck);
+        state.next = None;
+        if state.speed == 1.0 && state.pitch == 0.0 {
+            state.stretch = None;
+            state.resampler = None;
+        }
+
+        signal
+    }
+
+    // Queues the source to start once the current one ends
+    pub fn append(&self, mut source: TrackSource) -> mpsc::Receiver<()> {
+        let state = &mut *self.state.lock().unwrap();
+        source.set_blocking(state.blocking);
+        source.set_passthrough(state.passthrough);
+        let (deck, signal) = Deck::new(source);
+        state.next = Some(deck);
+        state.awaiting_next = None;
+
+        signal
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().is_ready()
+    }
+
+    pub fn wants_next(&self, lead: Duration) -> bool {
+        self.state.lock().unwrap().wants_next(lead)
+    }
+
+    // Offline outputs hold off within `lead` of the end until a track is
+    // appended, None once nothing is coming
+    pub fn set_awaiting_next(&self, lead: Option<Duration>) {
+        self.state.lock().unwrap().awaiting_next = lead;
+    }
+
+    pub fn rendered(&self) -> u64 {
+        self.rendered.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn subscribe_finished(&self) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        self.finished.lock().unwrap().push(tx);
+        rx
+    }
+
+    // Dropped receivers are forgotten
+    pub fn notify_finished(&self) {
+        self.finished
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(()).is_ok());
+    }
+
+    pub fn has_current(&self) -> bool {
+        self.state.lock().unwrap().current.is_some()
+    }
+
+    pub fn set_crossfade(&self, crossfade: Option<Crossfade>) {
+        self.state.lock().unwrap().crossfade = crossfade;
+    }
+
+    pub fn skip_crossfade(&self) -> Option<Crossfade> {
+        self.state.lock().unwrap().skip_crossfade
+    }
+
+    pub fn set_skip_crossfade(&self, crossfade: Option<Crossfade>) {
+        self.state.lock().unwrap().skip_crossfade = crossfade;
+    }
+
+    // Changes land between two rendered chunks so they never tear a buffer
+    pub fn effects<R>(&self, f: impl FnOnce(&mut EffectChain) -> R) -> R {
+        f(&mut self.state.lock().unwrap().effects)
+    }
+
+    pub fn equalizer<R>(&self, f: impl FnOnce(&mut Equalizer) -> R) -> R {
+        f(&mut self.state.lock().unwrap().equalizer)
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        let state = &mut *self.state.lock().unwrap();
+        state.speed = speed;
+        if speed != 1.0 && state.stretch.is_none() {
+            state.stretch = Some(Stretch::new(CHANNELS));
+        }
+    }
+
+    pub fn set_pitch(&self, semitones: f32) {
+        let state = &mut *self.state.lock().unwrap();
+        state.pitch = semitones;
+        if semitones != 0.0 && state.resampler.is_none() {
+            state.stretch.get_or_insert_with(|| Stretch::new(CHANNELS));
+            state.resampler = Some(Resampler::new(CHANNELS));
+        }
+        // Shifting up reads the input faster, what would go past the output's
+        // band is filtered out first
+        if let Some(ref mut resampler) = state.resampler {
+            resampler.set_low_pass(SAMPLE_RATE, 2f64.powf(semitones as f64 / 12.0));
+        }
+    }
+
+    pub fn spectrum_tap(&self) -> SpectrumTap {
+        self.state.lock().unwrap().spectrum.clone()
+    }
+
+    pub fn levels(&self) -> Arc<Mutex<Levels>> {
+        self.state.lock().unwrap().meter.levels()
+    }
+
+    pub fn reset_clips(&self) {
+        self.state.lock().unwrap().meter.reset_clips();
+    }
+
+    pub fn channels<R>(&self, f: impl FnOnce(&mut ChannelMapper) -> R) -> R {
+        f(&mut self.state.lock().unwrap().channels)
+    }
+
+    pub fn set_skip_silence(&self, skip_silence: Option<SkipSilence>) {
+        self.state.lock().unwrap().skip_silence = skip_silence;
+    }
+
+    pub fn set_equalizer_enabled(&self, enabled: bool) {
+        self.state.lock().unwrap().equalizer_enabled = enabled;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state.lock().unwrap().fader.target > 0.0
+    }
+
+    // Nothing audible is playing and no fade is waiting to finish
+    pub fn is_idle(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let fader = &state.fader;
+
+        fader.is_silent()
+            || (state.current.is_none() && state.outgoing.is_none() && fader.gain == fader.target)
+    }
+
+    pub fn set_blocking(&self, blocking: bool) {
+        let state = &mut *self.state.lock().unwrap();
+        state.blocking = blocking;
+        for deck in state.decks_mut() {
+            deck.source.set_blocking(blocking);
+        }
+    }
+
+    pub fn set_passthrough(&self, passthrough: bool) {
+        let state = &mut *self.state.lock().unwrap();
+        state.passthrough = passthrough;
+        for deck in state.decks_mut() {
+            deck.source.set_passthrough(passthrough);
+        }
+    }
+
+    pub fn set_fade(&self, duration: Duration) {
+        self.state.lock().unwrap().fader.duration = duration;
+    }
+
+    // Fades the output out and holds the decks once it's silent
+    pub async fn pause(&self) {
+        self.fade_to(0.0).await
+    }
+
+    pub async fn resume(&self) {
+        self.fade_to(1.0).await
+    }
+
+    async fn fade_to(&self, target: f32) {
+        let (signal, duration) = {
+            let fader = &mut self.state.lock().unwrap().fader;
+            fader.target = target;
+            if fader.gain == target {
+                return;
+            }
+
+            let (tx, rx) = oneshot::channel();
+            fader.waiters.push(tx);
+            (rx, fader.duration)
+        };
+
+        // Don't hang if the output stopped pulling samples
+        let timeout = duration * 2 + Duration::from_millis(500);
+        if tokio::time::timeout(timeout, signal).await.is_err() {
+            log::warn!("Fade didn't finish in {timeout:?}");
+        }
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.state.lock().unwrap().volume = volume;
+    }
+
+    // Existing instances are kept so their envelopes don't restart
+    pub fn set_compressor(&self, settings: Option<CompressorSettings>) {
+        let state = &mut *self.state.lock().unwrap();
+        match (settings, state.compressor.as_mut()) {
+            (Some(settings), Some(compressor)) => compressor.set_settings(settings),
+            (settings, _) => state.compressor = settings.map(Compressor::new),
+        }
+    }
+
+    pub fn set_limiter(&self, settings: Option<LimiterSettings>) {
+        let state = &mut *self.state.lock().unwrap();
+        match (settings, state.limiter.as_mut()) {
+            (Some(settings), Some(limiter)) => limiter.set_settings(settings),
+            (settings, _) => state.limiter = settings.map(Limiter::new),
+        }
+    }
+}
@@ -1,23 +1,67 @@
-use std::sync::{atomic, Arc};
+use std::{
+    collections::HashMap,
+    sync::{atomic, Arc},
+    time::Duration,
+};
 use tokio::sync::{
     mpsc::{channel, Sender},
     Mutex,
 };
 
-use crate::{Track, TrackSourceHandle, TracksQueue, TracksQueueHandle};
+use crate::{
+    dsp::SpectrumAnalyzer,
+    track::{Metadata, SAMPLE_RATE},
+    AudioEffect, Band, ChannelMap, Codec, CompressorSettings, DownloadEvent, EffectInfo,
+    EqualizerPreset, Levels, LimiterSettings, Loudness, Normalization, NormalizationMode,
+    SkipSilence, SpectrumConfig, Track, TrackSourceHandle, TracksQueue, TracksQueueHandle,
+    Waveform,
+};
+
+mod mixer;
+use mixer::MixerHandle;
+pub use mixer::{Crossfade, FadeCurve};
+
+mod output;
+use output::Device;
+pub use output::{
+    AudioSink, CustomSink, FileFormat, OpusFrame, OpusSender, Output, OutputDevice, SampleFormat,
+    Samples, SinkFormat,
+};
+
+// How long before the end of the current track, and its crossfade, the next
+// one gets loaded
+const PRELOAD: Duration = Duration::from_secs(10);
 
-// Need to make OutputStream send
-// I don't even use it. It need to be alive to keep audio alive
-struct OutS(rodio::OutputStream);
-unsafe impl Send for OutS {}
+const MIN_SPEED: f32 = 0.5;
+const MAX_SPEED: f32 = 3.0;
+const MAX_PITCH: f32 = 12.0;
+// Ticks of the poll loop between checks that the output device is still there
+const DEVICE_CHECK_TICKS: u32 = 10;
 
 pub enum Request {
     Play(Play, bool),
     Pause,
     Resume,
     SetVolume(f32),
-    Seek(u64),
+    Seek(Seek),
     Queue(Queue),
+    SetCrossfade(Option<Crossfade>),
+    SetSkipCrossfade(Option<Crossfade>),
+    SetNormalization(Option<Normalization>),
+    Effects(Effects),
+    Equalization(Equalization),
+    SetSpeed(f32),
+    // Semitones
+    SetPitch(f32),
+    SetSkipSilence(Option<SkipSilence>),
+    Channels(Channels),
+    SetSpectrum(SpectrumConfig),
+    SetCompressor(Option<CompressorSettings>),
+    SetLimiter(Option<LimiterSettings>),
+    // Length of the fades around pause, resume, seek and skip
+    SetFade(Duration),
+    // Moves playback to another output, position and preloaded track are kept
+    SetOutput(Output),
 }
 
 impl std::fmt::Display for Request {
@@ -26,9 +70,55 @@ impl std::fmt::Display for Request {
             Request::Pause => write!(f, "Pause"),
             Request::Play(ref inner, lazy) => write!(f, "Play(inner: {inner}, lazy: {lazy})"),
             Request::Resume => write!(f, "Resume"),
-            Request::Seek(pos) => write!(f, "Seek(ts: {pos})"),
+            Request::Seek(ref inner) => write!(f, "Seek(inner: {inner})"),
             Request::Queue(ref inner) => write!(f, "Queue(inner: {inner})"),
             Request::SetVolume(value) => write!(f, "SetVolume(value: {value})"),
+            Request::SetCrossfade(Some(crossfade)) => write!(f, "SetCrossfade({crossfade})"),
+            Request::SetCrossfade(None) => write!(f, "SetCrossfade(None)"),
+            Request::SetSkipCrossfade(Some(crossfade)) => {
+                write!(f, "SetSkipCrossfade({crossfade})")
+            }
+            Request::SetSkipCrossfade(None) => write!(f, "SetSkipCrossfade(None)"),
+            Request::SetNormalization(Some(normalization)) => {
+                write!(f, "SetNormalization({normalization})")
+            }
+            Request::SetNormalization(None) => write!(f, "SetNormalization(None)"),
+            Request::Effects(ref inner) => write!(f, "Effects(inner: {inner})"),
+            Request::Equalization(ref inner) => write!(f, "Equalization(inner: {inner})"),
+            Request::SetSpeed(value) => write!(f, "SetSpeed(value: {value})"),
+            Request::SetPitch(value) => write!(f, "SetPitch(value: {value})"),
+            Request::SetSkipSilence(Some(skip_silence)) => {
+                write!(f, "SetSkipSilence({skip_silence})")
+            }
+            Request::SetSkipSilence(None) => write!(f, "SetSkipSilence(None)"),
+            Request::Channels(ref inner) => write!(f, "Channels(inner: {inner})"),
+            Request::SetSpectrum(config) => write!(f, "SetSpectrum({config})"),
+            Request::SetCompressor(Some(settings)) => write!(f, "SetCompressor({settings})"),
+            Request::SetCompressor(None) => write!(f, "SetCompressor(None)"),
+            Request::SetLimiter(Some(settings)) => write!(f, "SetLimiter({settings})"),
+            Request::SetLimiter(None) => write!(f, "SetLimiter(None)"),
+            Request::SetFade(duration) => write!(f, "SetFade(duration: {duration:?})"),
+            Request::SetOutput(ref output) => write!(f, "SetOutput({output})"),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Seek {
+    To(Duration),
+    Forward(Duration),
+    Backward(Duration),
+    // 0 to 100
+    Percent(f32),
+}
+
+impl std::fmt::Display for Seek {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Seek::To(pos) => write!(f, "To(pos: {pos:?})"),
+            Seek::Forward(offset) => write!(f, "Forward(offset: {offset:?})"),
+            Seek::Backward(offset) => write!(f, "Backward(offset: {offset:?})"),
+            Seek::Percent(value) => write!(f, "Percent(value: {value})"),
         }
     }
 }
@@ -71,17 +161,120 @@ impl std::fmt::Display for Queue {
     }
 }
 
+pub enum Effects {
+    Add(Box<dyn AudioEffect>),
+    Remove(usize),
+    Move(usize, usize),
+    Bypass(usize, bool),
+    SetParam(usize, String, f32),
+    Clear,
+}
+
+impl std::fmt::Display for Effects {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Effects::Add(ref effect) => write!(f, "Add(name: {})", effect.name()),
+            Effects::Remove(pos) => write!(f, "Remove(pos: {pos})"),
+            Effects::Move(from, to) => write!(f, "Move(from: {from}, to: {to})"),
+            Effects::Bypass(pos, bypass) => write!(f, "Bypass(pos: {pos}, bypass: {bypass})"),
+            Effects::SetParam(pos, ref name, value) => {
+                write!(f, "SetParam(pos: {pos}, name: {name}, value: {value})")
+            }
+            Effects::Clear => write!(f, "Clear"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Equalization {
+    Enable(bool),
+    // Resets to the flat 10-band graphic equalizer
+    Graphic,
+    SetGain(usize, f32),
+    SetPreamp(f32),
+    // Switches to parametric mode with the given bands
+    SetBands(Vec<Band>),
+    SetBand(usize, Band),
+    SavePreset(String),
+    LoadPreset(String),
+    DeletePreset(String),
+}
+
+impl std::fmt::Display for Equalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Equalization::Enable(enabled) => write!(f, "Enable(enabled: {enabled})"),
+            Equalization::Graphic => write!(f, "Graphic"),
+            Equalization::SetGain(pos, gain) => write!(f, "SetGain(pos: {pos}, gain: {gain})"),
+            Equalization::SetPreamp(gain) => write!(f, "SetPreamp(gain: {gain})"),
+            Equalization::SetBands(ref bands) => write!(f, "SetBands(len: {})", bands.len()),
+            Equalization::SetBand(pos, ref band) => {
+                write!(f, "SetBand(pos: {pos}, band: {band:?})")
+            }
+            Equalization::SavePreset(ref name) => write!(f, "SavePreset(name: {name})"),
+            Equalization::LoadPreset(ref name) => write!(f, "LoadPreset(name: {name})"),
+            Equalization::DeletePreset(ref name) => write!(f, "DeletePreset(name: {name})"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Channels {
+    SetBalance(f32),
+    Swap(bool),
+    Mono(bool),
+    // Rows are output channels, columns are input channels
+    SetMatrix(Option<Vec<Vec<f32>>>),
+    Reset,
+}
+
+impl std::fmt::Display for Channels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Channels::SetBalance(value) => write!(f, "SetBalance(value: {value})"),
+            Channels::Swap(swap) => write!(f, "Swap(swap: {swap})"),
+            Channels::Mono(mono) => write!(f, "Mono(mono: {mono})"),
+            Channels::SetMatrix(ref matrix) => write!(f, "SetMatrix(matrix: {matrix:?})"),
+            Channels::Reset => write!(f, "Reset"),
+        }
+    }
+}
+
+type WaveformCache = HashMap<(Arc<str>, usize), Arc<Waveform>>;
+
 pub struct AudioManager {
     rt: Arc<tokio::runtime::Runtime>,
     current_track: Arc<Mutex<Option<TrackSourceHandle>>>,
     queue: Arc<Mutex<TracksQueue>>,
     is_playing: Arc<atomic::AtomicBool>,
+    mixer: MixerHandle,
+    presets: Arc<Mutex<HashMap<String, EqualizerPreset>>>,
+    spectrum: Arc<Mutex<SpectrumAnalyzer>>,
+    waveforms: Arc<Mutex<WaveformCache>>,
+    levels: Arc<std::sync::Mutex<Levels>>,
+    device: Arc<Mutex<Option<Device>>>,
     tx: Sender<Request>,
 }
 
 impl AudioManager {
     pub fn new(rt: Arc<tokio::runtime::Runtime>) -> Self {
-        AudioHandler::start(rt)
+        AudioHandler::start(rt, Output::Default)
+    }
+
+    pub fn with_output(rt: Arc<tokio::runtime::Runtime>, output: Output) -> Self {
+        AudioHandler::start(rt, output)
+    }
+
+    pub fn output_devices() -> Vec<OutputDevice> {
+        output::devices()
+    }
+
+    // The output in use, Default also after falling back to it
+    pub fn output(&self) -> Output {
+        self.rt
+            .block_on(self.device.lock())
+            .as_ref()
+            .map_or(Output::Default, |device| device.output().clone())
     }
 
     pub fn queue(&self) -> TracksQueueHandle {
@@ -99,46 +292,226 @@ impl AudioManager {
         self.is_playing.load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    pub fn effects(&self) -> Vec<EffectInfo> {
+        self.mixer.effects(|effects| effects.list())
+    }
+
+    // Levels in dBFS of what is currently playing, after all processing
+    pub fn spectrum(&self) -> Vec<f32> {
+        self.rt.block_on(self.spectrum.lock()).spectrum()
+    }
+
+    // Output levels after all processing, updated every rendered chunk
+    pub fn levels(&self) -> Levels {
+        self.levels.lock().unwrap().clone()
+    }
+
+    pub fn reset_clips(&self) {
+        self.mixer.reset_clips()
+    }
+
+    // Fires each time a file output has written everything it was given, once
+    // the queue ends or playback is paused
+    pub fn finished(&self) -> std::sync::mpsc::Receiver<()> {
+        self.mixer.subscribe_finished()
+    }
+
+    // Cached per track, an unfinished waveform is returned as is and a failed
+    // one is started over
+    pub fn waveform(&self, track: &Track, buckets: usize) -> Arc<Waveform> {
+        let waveforms = &mut *self.rt.block_on(self.waveforms.lock());
+        let key = (track.id.clone(), buckets);
+
+        match waveforms.get(&key) {
+            Some(waveform) if !waveform.is_failed() => waveform.clone(),
+            _ => {
+                let waveform = track.waveform(self.rt.handle(), buckets);
+                waveforms.insert(key, waveform.clone());
+                waveform
+            }
+        }
+    }
+
+    // Saves the track next to playback, events end with Done or Failed
+    pub fn download(
+        &self,
+        track: &Track,
+        path: impl Into<std::path::PathBuf>,
+    ) -> std::sync::mpsc::Receiver<DownloadEvent> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let track = track.clone();
+        let path = path.into();
+
+        self.rt.spawn(async move {
+            let progress = {
+                let tx = tx.clone();
+                move |value| _ = tx.send(DownloadEvent::Progress(value))
+            };
+
+            _ = tx.send(match track.download(path, progress).await {
+                Some(()) => DownloadEvent::Done,
+                None => DownloadEvent::Failed,
+            });
+        });
+
+        rx
+    }
+
+    // Same events as a download, the file is re-encoded instead of copied
+    pub fn transcode(
+        &self,
+        track: &Track,
+        path: impl Into<std::path::PathBuf>,
+        codec: Codec,
+    ) -> std::sync::mpsc::Receiver<DownloadEvent> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let track = track.clone();
+        let path = path.into();
+
+        self.rt.spawn(async move {
+            let progress = {
+                let tx = tx.clone();
+                move |value| _ = tx.send(DownloadEvent::Progress(value))
+            };
+
+            _ = tx.send(match track.transcode(path, codec, progress).await {
+                Some(()) => DownloadEvent::Done,
+                None => DownloadEvent::Failed,
+            });
+        });
+
+        rx
+    }
+
+    pub fn channel_map(&self) -> ChannelMap {
+        self.mixer.channels(|channels| channels.map().clone())
+    }
+
+    pub fn equalizer(&self) -> EqualizerPreset {
+        self.mixer.equalizer(|equalizer| equalizer.preset().clone())
+    }
+
+    pub fn equalizer_presets(&self) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .rt
+            .block_on(self.presets.lock())
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+
+        names
+    }
+
+    // One preset per line, the name and `EqualizerPreset::to_line` separated by
+    // a tab. Names that would break the line are left out
+    pub fn export_equalizer_presets(&self, path: impl AsRef<std::path::Path>) -> Option<()> {
+        let presets = self.rt.block_on(self.presets.lock());
+        let mut names: Vec<_> = presets.keys().collect();
+        names.sort();
+
+        let mut text = String::new();
+        for name in names {
+            if name.contains(['\t', '\n', '\r']) {
+                log::warn!("Can't export equalizer preset {name:?}");
+                continue;
+            }
+            text += &format!("{name}\t{}\n", presets[name].to_line());
+        }
+
+        std::fs::write(path, text).map_err(|e| dbg!(e)).ok()
+    }
+
+    // Adds the presets of an export, replacing ones with the same name.
+    // Lines that don't parse are skipped, returns how many were added
+    pub fn import_equalizer_presets(&self, path: impl AsRef<std::path::Path>) -> Option<usize> {
+        let text = std::fs::read_to_string(path).map_err(|e| dbg!(e)).ok()?;
+        let imported: Vec<_> = text
+            .lines()
+            .filter_map(|line| {
+                let (name, preset) = line.split_once('\t')?;
+                Some((name.to_string(), EqualizerPreset::from_line(preset)?))
+            })
+            .collect();
+
+        let count = imported.len();
+        self.rt.block_on(self.presets.lock()).extend(imported);
+
+        Some(count)
+    }
+
     pub fn send(&self, request: Request) {
         self.rt.block_on(self.tx.send(request)).unwrap();
     }
 }
 
+impl Drop for AudioManager {
+    // The poll loop keeps the device alive, closing it here finishes files
+    fn drop(&mut self) {
+        match self.device.try_lock() {
+            Ok(mut device) => drop(device.take()),
+            Err(_) => {
+                let device = self.device.clone();
+                self.rt.spawn(async move {
+                    device.lock().await.take();
+                });
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 struct Context {
     pub current_track: Arc<Mutex<Option<TrackSourceHandle>>>,
     pub current_signal: Arc<Mutex<Option<std::sync::mpsc::Receiver<()>>>>,
     pub next_track: Arc<Mutex<Option<TrackSourceHandle>>>,
     pub next_signal: Arc<Mutex<Option<std::sync::mpsc::Receiver<()>>>>,
-    pub sink: Arc<rodio::Sink>,
     pub is_playing: Arc<atomic::AtomicBool>,
     pub queue: Arc<Mutex<TracksQueue>>,
-    pub input: Arc<Mutex<Option<Arc<rodio::queue::SourcesQueueInput<f32>>>>>,
+    pub mixer: MixerHandle,
+    pub normalization: Arc<Mutex<Option<Normalization>>>,
+    // None while the analysis is still running
+    pub loudness: Arc<Mutex<HashMap<Arc<str>, Option<Loudness>>>>,
+    pub presets: Arc<Mutex<HashMap<String, EqualizerPreset>>>,
+    pub spectrum: Arc<Mutex<SpectrumAnalyzer>>,
+    // None only while switching
+    pub device: Arc<Mutex<Option<Device>>>,
 }
 
 struct AudioHandler;
 
 impl AudioHandler {
-    pub fn start(rt: Arc<tokio::runtime::Runtime>) -> AudioManager {
+    pub fn start(rt: Arc<tokio::runtime::Runtime>, output: Output) -> AudioManager {
         let current_track = Arc::new(Mutex::new(None));
         let current_signal = Arc::new(Mutex::new(None));
         let next_track = Arc::new(Mutex::new(None));
         let next_signal = Arc::new(Mutex::new(None));
         let queue = Arc::new(Mutex::new(TracksQueue::new()));
         let is_playing = Arc::new(atomic::AtomicBool::new(false));
-        let (output, output_handle) = rodio::OutputStream::try_default().unwrap();
-        let sink = Arc::new(rodio::Sink::try_new(&output_handle).unwrap());
-        let input = Arc::new(Mutex::new(None));
+        let mixer_handle = MixerHandle::new();
+        let device = Device::open(output, &mixer_handle);
 
         let ctx = Context {
             current_track,
             current_signal,
             next_track,
             next_signal,
-            sink,
             is_playing,
             queue,
-            input,
+            spectrum: Arc::new(Mutex::new(SpectrumAnalyzer::new(
+                mixer_handle.spectrum_tap(),
+                SAMPLE_RATE,
+            ))),
+            device: Arc::new(Mutex::new(Some(device))),
+            mixer: mixer_handle,
+            normalization: Arc::new(Mutex::new(None)),
+            loudness: Arc::new(Mutex::new(HashMap::new())),
+            presets: Arc::new(Mutex::new(
+                EqualizerPreset::builtin()
+                    .into_iter()
+                    .map(|(name, preset)| (name.to_string(), preset))
+                    .collect(),
+            )),
         };
 
         let (tx, mut rx) = channel(20);
@@ -146,6 +519,10 @@ impl AudioHandler {
             current_track,
             queue,
             is_playing,
+            mixer,
+            presets,
+            spectrum,
+            device,
             ..
         } = ctx.clone();
 
@@ -156,29 +533,40 @@ impl AudioHandler {
                 current_signal,
                 next_track,
                 next_signal,
-                sink,
                 is_playing,
                 queue,
+                mixer,
+                device,
                 ..
             } = ctx.clone();
 
             async move {
+                let mut ticks = 0;
                 loop {
                     tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
-                    is_playing.store(!sink.is_paused(), std::sync::atomic::Ordering::Relaxed);
-                    if let Some(ref track) = *current_track.lock().await {
-                        let current_time = track.current_time();
-                        let total_duration = track.metadata().duration;
+                    ticks += 1;
+                    if ticks == DEVICE_CHECK_TICKS {
+                        ticks = 0;
+                        let missing = device.lock().await.as_mut().and_then(|device| {
+                            (!device.is_present()).then(|| device.output().clone())
+                        });
 
-                        if total_duration.saturating_sub(current_time) < 10
-                            && next_track.lock().await.is_none()
-                            && queue.lock().await.peek_next().is_some()
-                        {
-                            tx.send(Request::Play(Play::Next, true)).await.unwrap();
+                        if let Some(output) = missing {
+                            log::warn!("Output {output} disappeared, opening the default");
+                            tx.send(Request::SetOutput(Output::Default)).await.unwrap();
                         }
                     }
 
+                    is_playing.store(mixer.is_playing(), std::sync::atomic::Ordering::Relaxed);
+                    // Decided by the mixer, offline outputs wait on the same check
+                    if mixer.wants_next(PRELOAD)
+                        && next_track.lock().await.is_none()
+                        && queue.lock().await.peek_next().is_some()
+                    {
+                        tx.send(Request::Play(Play::Next, true)).await.unwrap();
+                    }
+
                     if let Some(ref mut current_signal) = *current_signal.lock().await {
                         match current_signal.try_recv() {
                             Ok(()) => {
@@ -196,8 +584,12 @@ impl AudioHandler {
                                 if let (Some(signal), Some(track)) = bundle {
                                     *current_signal = signal;
                                     *current_track.lock().await = Some(track);
+                                } else if queue.lock().await.current().is_some() {
+                                    // It ended before the next one got preloaded,
+                                    // offline outputs easily get there that fast
+                                    tx.send(Request::Play(Play::Current, false)).await.unwrap();
                                 } else {
-                                    sink.pause();
+                                    mixer.pause().await;
                                 }
                             }
                             Err(std::sync::mpsc::TryRecvError::Disconnected) => {
@@ -210,7 +602,7 @@ impl AudioHandler {
                                     *current_signal = signal;
                                     *current_track.lock().await = Some(track);
                                 } else {
-                                    sink.pause();
+                                    mixer.pause().await;
                                 }
                             }
                             _ => {}
@@ -220,11 +612,7 @@ impl AudioHandler {
             }
         });
 
-        let output = OutS(output);
         rt.spawn(async move {
-            // This can not be droped
-            let _output = output;
-
             loop {
                 let Some(request) = rx.recv().await else {
                     panic!("gui siadlo")
@@ -237,9 +625,33 @@ impl AudioHandler {
                         Request::Pause => Self::pause(ctx).await,
                         Request::Play(request, lazy) => Self::play(ctx, request, lazy).await,
                         Request::Resume => Self::resume(ctx).await,
-                        Request::Seek(pos) => Self::seek(ctx, pos).await,
+                        Request::Seek(request) => Self::seek(ctx, request).await,
                         Request::Queue(request) => Self::queue(ctx, request).await,
                         Request::SetVolume(value) => Self::set_volume(ctx, value).await,
+                        Request::SetCrossfade(crossfade) => {
+                            Self::set_crossfade(ctx, crossfade).await
+                        }
+                        Request::SetSkipCrossfade(crossfade) => {
+                            Self::set_skip_crossfade(ctx, crossfade).await
+                        }
+                        Request::SetNormalization(normalization) => {
+                            Self::set_normalization(ctx, normalization).await
+                        }
+                        Request::Effects(request) => Self::effects(ctx, request).await,
+                        Request::Equalization(request) => Self::equalization(ctx, request).await,
+                        Request::SetSpeed(value) => Self::set_speed(ctx, value).await,
+                        Request::SetPitch(value) => Self::set_pitch(ctx, value).await,
+                        Request::SetSkipSilence(skip_silence) => {
+                            Self::set_skip_silence(ctx, skip_silence).await
+                        }
+                        Request::Channels(request) => Self::channels(ctx, request).await,
+                        Request::SetSpectrum(config) => Self::set_spectrum(ctx, config).await,
+                        Request::SetCompressor(settings) => {
+                            Self::set_compressor(ctx, settings).await
+                        }
+                        Request::SetLimiter(settings) => Self::set_limiter(ctx, settings).await,
+                        Request::SetFade(duration) => Self::set_fade(ctx, duration).await,
+                        Request::SetOutput(output) => Self::set_output(ctx, output).await,
                     }
                 });
             }
@@ -250,14 +662,20 @@ impl AudioHandler {
             current_track,
             queue,
             is_playing,
+            presets,
+            spectrum,
+            waveforms: Default::default(),
+            levels: mixer.levels(),
+            mixer,
+            device,
             tx,
         }
     }
 
     async fn pause(ctx: Context) {
-        let Context { sink, .. } = ctx;
+        let Context { mixer, .. } = ctx;
 
-        sink.pause()
+        mixer.pause().await
     }
 
     async fn play(ctx: Context, request: Play, lazy: bool) {
@@ -266,11 +684,10 @@ impl AudioHandler {
             current_signal,
             next_track,
             next_signal,
-            sink,
             queue,
-            input,
+            mixer,
             ..
-        } = ctx;
+        } = ctx.clone();
 
         let track = {
             let queue_lock = &mut queue.lock().await;
@@ -287,82 +704,369 @@ impl AudioHandler {
         };
 
         if let Some(track) = track {
-            if lazy && next_track.lock().await.is_none() && input.lock().await.is_some() {
-                let Some((source, source_handle)) = track.start().await else {
-                    return;
-                };
+            Self::analyze(ctx.clone(), track.clone());
 
+            if lazy && next_track.lock().await.is_none() && mixer.has_current() {
                 {
                     let queue = &mut queue.lock().await;
                     let pos = queue.pos() - 1;
                     queue.set_pos(pos)
                 }
 
-                {
-                    let input_lock = input.lock().await;
-                    let input = input_lock.as_ref().unwrap();
-                    *next_signal.lock().await = Some(input.append_with_signal(source));
-                }
+                let Some((source, source_handle)) = track.start().await else {
+                    // Offline outputs play on, the end falls back to `Play::Current`
+                    mixer.set_awaiting_next(None);
+                    return;
+                };
 
+                // Set before the mixer takes it, so it doesn't ramp from unity
+                source_handle.set_gain(Self::gain(&ctx, &source_handle.metadata()).await);
+                *next_signal.lock().await = Some(mixer.append(source));
                 *next_track.lock().await = Some(source_handle);
+                Self::await_next(&ctx).await;
             } else {
                 let Some((source, source_handle)) = track.start().await else {
                     return;
                 };
 
-                let (new_input, output) = rodio::queue::queue::<f32>(true);
-
-                sink.stop();
-                sink.append(output);
-                sink.play();
+                // A skip crossfade already fades the old track out. Fading
+                // out nothing would put silence into offline outputs
+                if mixer.skip_crossfade().is_none() && !mixer.is_idle() {
+                    mixer.pause().await;
+                }
 
-                *current_signal.lock().await = Some(new_input.append_with_signal(source));
+                source_handle.set_gain(Self::gain(&ctx, &source_handle.metadata()).await);
+                *current_signal.lock().await = Some(mixer.play(source));
                 *current_track.lock().await = Some(source_handle);
                 *next_signal.lock().await = None;
                 *next_track.lock().await = None;
-                *input.lock().await = Some(new_input);
+                Self::await_next(&ctx).await;
+                mixer.resume().await;
             }
         }
     }
 
     async fn resume(ctx: Context) {
-        let Context { sink, .. } = ctx;
+        let Context { mixer, .. } = ctx;
 
-        sink.play()
+        mixer.resume().await
     }
 
-    async fn seek(ctx: Context, pos: u64) {
+    async fn seek(ctx: Context, request: Seek) {
         let Context {
-            sink,
+            mixer,
             current_track,
             ..
         } = ctx;
 
         if let Some(track) = &*current_track.lock().await {
-            sink.pause();
-            track.seek(pos, 0.0);
-            sink.play();
+            let duration = Duration::from_secs(track.metadata().duration);
+            let pos = match request {
+                Seek::To(pos) => pos,
+                Seek::Forward(offset) => track.position() + offset,
+                Seek::Backward(offset) => track.position().saturating_sub(offset),
+                Seek::Percent(value) => duration.mul_f32(value.clamp(0.0, 100.0) / 100.0),
+            }
+            .min(duration);
+
+            let playing = mixer.is_playing();
+            if playing {
+                mixer.pause().await;
+            }
+            if track.seek(pos).is_none() {
+                log::warn!("Seeking to {pos:?} failed");
+            }
+            if playing {
+                mixer.resume().await;
+            }
         };
     }
 
+    async fn set_output(ctx: Context, output: Output) {
+        let Context { mixer, device, .. } = ctx;
+        let device = &mut *device.lock().await;
+
+        // Fading nothing out would still render silence into offline outputs
+        let playing = mixer.is_playing() && !mixer.is_idle();
+        if playing {
+            mixer.pause().await;
+        }
+
+        // The old one has to stop pulling from the mixer before the new one starts
+        *device = None;
+        *device = Some(Device::open(output, &mixer));
+
+        if playing {
+            mixer.resume().await;
+        }
+    }
+
+    async fn set_fade(ctx: Context, duration: Duration) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_fade(duration)
+    }
+
     async fn queue(ctx: Context, request: Queue) {
-        let Context { queue, .. } = ctx;
+        let Context { queue, .. } = ctx.clone();
 
         match request {
             Queue::Clear => queue.lock().await.clear(),
             Queue::Add(url) => {
                 if let Some(track) = Track::new(url).await {
-                    queue.lock().await.push_back(track);
+                    queue.lock().await.push_back(track.clone());
+
+                    // Measured ahead of time, so the gain is known before the
+                    // track starts. Album gain is measured over the whole queue
+                    Self::analyze(ctx.clone(), track);
                 }
             }
             Queue::Remove(pos) => _ = queue.lock().await.remove(pos),
             Queue::Swap(from, to) => queue.lock().await.swap(from, to),
         }
+
+        Self::await_next(&ctx).await;
+    }
+
+    // Offline outputs wait near the end of the track the mixer is heading for
+    // while the queue has one to follow it, so the next track is always
+    // appended in time for the crossfade however fast they render
+    async fn await_next(ctx: &Context) {
+        let appended = ctx.next_track.lock().await.is_some() as usize;
+        let queue = ctx.queue.lock().await;
+        let pending = queue.pos() + appended + 1 < queue.len();
+
+        ctx.mixer.set_awaiting_next(pending.then_some(PRELOAD));
     }
 
+    // Volume is applied in the mixer so the limiter comes after it
     async fn set_volume(ctx: Context, value: f32) {
-        let Context { sink, .. } = ctx;
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_volume(value.max(0.0))
+    }
+
+    async fn set_compressor(ctx: Context, settings: Option<CompressorSettings>) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_compressor(settings)
+    }
+
+    async fn set_limiter(ctx: Context, settings: Option<LimiterSettings>) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_limiter(settings)
+    }
+
+    async fn set_crossfade(ctx: Context, crossfade: Option<Crossfade>) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_crossfade(crossfade)
+    }
+
+    async fn set_skip_crossfade(ctx: Context, crossfade: Option<Crossfade>) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_skip_crossfade(crossfade)
+    }
+
+    async fn effects(ctx: Context, request: Effects) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.effects(|effects| match request {
+            Effects::Add(effect) => effects.add(effect),
+            Effects::Remove(pos) => effects.remove(pos),
+            Effects::Move(from, to) => effects.move_to(from, to),
+            Effects::Bypass(pos, bypass) => effects.bypass(pos, bypass),
+            Effects::SetParam(pos, name, value) => {
+                if !effects.set_param(pos, &name, value) {
+                    log::warn!("Effect {pos} has no parameter {name}");
+                }
+            }
+            Effects::Clear => effects.clear(),
+        })
+    }
+
+    async fn set_speed(ctx: Context, value: f32) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_speed(value.clamp(MIN_SPEED, MAX_SPEED))
+    }
+
+    async fn set_pitch(ctx: Context, value: f32) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_pitch(value.clamp(-MAX_PITCH, MAX_PITCH))
+    }
+
+    async fn set_skip_silence(ctx: Context, skip_silence: Option<SkipSilence>) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.set_skip_silence(skip_silence)
+    }
+
+    async fn set_spectrum(ctx: Context, config: SpectrumConfig) {
+        let Context { spectrum, .. } = ctx;
+
+        spectrum.lock().await.set_config(config);
+    }
+
+    async fn channels(ctx: Context, request: Channels) {
+        let Context { mixer, .. } = ctx;
+
+        mixer.channels(|channels| {
+            let mut map = channels.map().clone();
+            match request {
+                Channels::SetBalance(value) => map.balance = value.clamp(-1.0, 1.0),
+                Channels::Swap(swap) => map.swap = swap,
+                Channels::Mono(mono) => map.mono = mono,
+                Channels::SetMatrix(matrix) => map.matrix = matrix,
+                Channels::Reset => map = ChannelMap::default(),
+            }
+            channels.set_map(map);
+        })
+    }
+
+    async fn equalization(ctx: Context, request: Equalization) {
+        let Context { mixer, presets, .. } = ctx;
+
+        match request {
+            Equalization::Enable(enabled) => mixer.set_equalizer_enabled(enabled),
+            Equalization::Graphic => {
+                mixer.equalizer(|equalizer| equalizer.load(EqualizerPreset::graphic([0.0; 10])))
+            }
+            Equalization::SetGain(pos, gain) => {
+                mixer.equalizer(|equalizer| equalizer.set_gain(pos, gain))
+            }
+            Equalization::SetPreamp(gain) => {
+                mixer.equalizer(|equalizer| equalizer.set_preamp(gain))
+            }
+            Equalization::SetBands(bands) => mixer.equalizer(|equalizer| {
+                let preamp = equalizer.preset().preamp;
+                equalizer.load(EqualizerPreset { preamp, bands })
+            }),
+            Equalization::SetBand(pos, band) => {
+                mixer.equalizer(|equalizer| equalizer.set_band(pos, band))
+            }
+            Equalization::SavePreset(name) => {
+                let preset = mixer.equalizer(|equalizer| equalizer.preset().clone());
+                presets.lock().await.insert(name, preset);
+            }
+            Equalization::LoadPreset(name) => match presets.lock().await.get(&name) {
+                Some(preset) => mixer.equalizer(|equalizer| equalizer.load(preset.clone())),
+                None => log::warn!("No equalizer preset named {name}"),
+            },
+            Equalization::DeletePreset(name) => _ = presets.lock().await.remove(&name),
+        }
+    }
+
+    async fn set_normalization(ctx: Context, value: Option<Normalization>) {
+        let Context {
+            normalization,
+            queue,
+            ..
+        } = ctx.clone();
+
+        *normalization.lock().await = value;
+
+        if value.is_some() {
+            let tracks = queue.lock().await.handle();
+            for track in tracks.list() {
+                Self::analyze(ctx.clone(), track.clone());
+            }
+        }
+
+        // Asked for, so the playing track changes as well
+        Self::normalize(ctx).await;
+    }
+
+    // Measures the track loudness in the background unless it is already known
+    fn analyze(ctx: Context, track: Track) {
+        tokio::spawn(async move {
+            let Context {
+                normalization,
+                loudness,
+                ..
+            } = ctx.clone();
+
+            if normalization.lock().await.is_none() {
+                return;
+            }
+
+            {
+                let mut loudness = loudness.lock().await;
+                if loudness.contains_key(&track.id) {
+                    return;
+                }
+                loudness.insert(track.id.clone(), None);
+            }
+
+            match track.loudness().await {
+                Some(value) => _ = loudness.lock().await.insert(track.id.clone(), Some(value)),
+                // Let the next play try again
+                None => _ = loudness.lock().await.remove(&track.id),
+            }
+
+            Self::normalize_next(ctx).await;
+        });
+    }
+
+    // A new measurement only goes to the preloaded track, the playing one
+    // would jump in level partway through
+    async fn normalize_next(ctx: Context) {
+        if let Some(ref handle) = *ctx.next_track.lock().await {
+            handle.set_gain(Self::gain(&ctx, &handle.metadata()).await);
+        }
+    }
+
+    // Applies the normalization gain to the playing and preloaded tracks
+    async fn normalize(ctx: Context) {
+        let Context {
+            current_track,
+            next_track,
+            ..
+        } = ctx.clone();
+
+        for handle in [&*current_track.lock().await, &*next_track.lock().await]
+            .into_iter()
+            .flatten()
+        {
+            handle.set_gain(Self::gain(&ctx, &handle.metadata()).await);
+        }
+    }
+
+    // 1.0 without normalization or while the loudness isn't known yet
+    async fn gain(ctx: &Context, metadata: &Metadata) -> f32 {
+        let Context {
+            queue,
+            normalization,
+            loudness,
+            ..
+        } = ctx;
+
+        let normalization = *normalization.lock().await;
+        let loudness = loudness.lock().await;
+        let measured = |id: &Arc<str>| loudness.get(id).copied().flatten();
+
+        let album = Loudness::album(
+            queue
+                .lock()
+                .await
+                .handle()
+                .list()
+                .iter()
+                .filter_map(|track| measured(&track.id)),
+        );
+
+        let track = metadata.replay_gain.or_else(|| measured(&metadata.id));
+
+        normalization.map_or(1.0, |normalization| {
+            let value = match normalization.mode {
+                NormalizationMode::Track => track,
+                NormalizationMode::Album => metadata.album_replay_gain.or(album).or(track),
+            };
 
-        sink.set_volume(value)
+            value.map_or(1.0, |value| normalization.gain(value))
+        })
     }
 }
@@ -0,0 +1,149 @@
+use std::sync::{atomic, Arc, Mutex};
+
+use super::{fill, wait};
+use crate::{
+    dsp::Resampler,
+    manager::{
+        mixer::{Mixer, CHUNK_FRAMES},
+        MixerHandle,
+    },
+    track::{CHANNELS, SAMPLE_RATE},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    F32,
+    I16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinkFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: SampleFormat,
+}
+
+impl std::fmt::Display for SinkFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SinkFormat(rate: {}, channels: {}, format: {:?})",
+            self.sample_rate, self.channels, self.sample_format
+        )
+    }
+}
+
+// Interleaved frames in the format the sink asked for
+#[derive(Debug, Clone, Copy)]
+pub enum Samples<'a> {
+    F32(&'a [f32]),
+    I16(&'a [i16]),
+}
+
+// Takes the mixed audio instead of a sound card, for embedding the player in
+// other pipelines
+pub trait AudioSink: Send {
+    // Asked once, when the sink is wrapped in a `CustomSink`
+    fn format(&self) -> SinkFormat;
+
+    // Called from the output thread, blocking paces the output. Returning
+    // false closes the output
+    fn write(&mut self, samples: Samples<'_>) -> bool;
+}
+
+// A user sink as an output, only equal to its own clones
+#[derive(Clone)]
+pub struct CustomSink {
+    sink: Arc<Mutex<Box<dyn AudioSink>>>,
+    format: SinkFormat,
+}
+
+impl CustomSink {
+    pub fn new(sink: impl AudioSink + 'static) -> Self {
+        Self {
+            format: sink.format(),
+            sink: Arc::new(Mutex::new(Box::new(sink))),
+        }
+    }
+
+    pub fn format(&self) -> SinkFormat {
+        self.format
+    }
+}
+
+impl PartialEq for CustomSink {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.sink, &other.sink)
+    }
+}
+
+impl std::fmt::Debug for CustomSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomSink({:?})", self.format)
+    }
+}
+
+pub(super) fn run(
+    mut source: Mixer,
+    mixer: MixerHandle,
+    sink: CustomSink,
+    stop: Arc<atomic::AtomicBool>,
+) {
+    let format = sink.format;
+    let channels = format.channels.max(1) as usize;
+    let ratio = SAMPLE_RATE as f64 / format.sample_rate.max(1) as f64;
+    let mut resampler = (format.sample_rate != SAMPLE_RATE)
+        .then(|| Resampler::with_rates(CHANNELS, SAMPLE_RATE, format.sample_rate));
+
+    // Frames handed to the sink per write, at its own rate
+    let mut buf = vec![0.0; CHUNK_FRAMES * CHANNELS as usize];
+    let mut out = Vec::with_capacity(CHUNK_FRAMES * channels);
+    let mut ints = Vec::with_capacity(CHUNK_FRAMES * channels);
+
+    while wait(&mixer, &stop) {
+        match resampler {
+            // Low rates pull several chunks per write, each one waited for.
+            // Once stopped the rest is whatever the mixer renders
+            Some(ref mut resampler) => resampler.render(&mut buf, ratio, |input| {
+                wait(&mixer, &stop);
+                fill(&mut source, input)
+            }),
+            None => fill(&mut source, &mut buf),
+        }
+        remix(&buf, channels, &mut out);
+
+        let sink = &mut *sink.sink.lock().unwrap();
+        let open = match format.sample_format {
+            SampleFormat::F32 => sink.write(Samples::F32(&out)),
+            SampleFormat::I16 => {
+                ints.clear();
+                ints.extend(
+                    out.iter()
+                        .map(|sample| (sample * 32768.0).clamp(-32768.0, 32767.0) as i16),
+                );
+                sink.write(Samples::I16(&ints))
+            }
+        };
+
+        if !open {
+            log::warn!("Custom output closed");
+            return;
+        }
+    }
+}
+
+// Stereo to the sink's channels: averaged for mono, extra channels are silent
+fn remix(buf: &[f32], channels: usize, out: &mut Vec<f32>) {
+    out.clear();
+
+    for frame in buf.chunks_exact(CHANNELS as usize) {
+        match channels {
+            1 => out.push(frame.iter().sum::<f32>() / CHANNELS as f32),
+            _ => {
+                let len = out.len();
+                out.extend(frame.iter().take(channels));
+                out.resize(len + channels, 0.0);
+            }
+        }
+    }
+}
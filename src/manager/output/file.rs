@@ -0,0 +1,117 @@
+use std::{
+    path::Path,
+    sync::{atomic, Arc},
+};
+
+use super::render;
+use crate::{
+    encode::FlacWriter,
+    manager::{
+        mixer::{Mixer, CHUNK_FRAMES},
+        MixerHandle,
+    },
+    track::{CHANNELS, SAMPLE_RATE},
+};
+
+// The header is brought up to date about once a second
+const FLUSH_CHUNKS: usize = SAMPLE_RATE as usize / CHUNK_FRAMES;
+const FLAC_BITS: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    // 32 bit float, exactly what the mixer renders
+    Wav,
+    // 24 bit
+    Flac,
+}
+
+impl std::fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            FileFormat::Wav => write!(f, "Wav"),
+            FileFormat::Flac => write!(f, "Flac"),
+        }
+    }
+}
+
+pub(super) fn run(
+    source: Mixer,
+    mixer: MixerHandle,
+    path: &Path,
+    format: FileFormat,
+    stop: Arc<atomic::AtomicBool>,
+) {
+    let result = match format {
+        FileFormat::Wav => write_wav(source, mixer, path, stop),
+        FileFormat::Flac => write_flac(source, mixer, path, stop),
+    };
+
+    if let Err(e) = result {
+        log::warn!("Writing {} failed: {e}", path.display());
+    }
+}
+
+fn write_wav(
+    mut source: Mixer,
+    mixer: MixerHandle,
+    path: &Path,
+    stop: Arc<atomic::AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = hound::WavSpec {
+        channels: CHANNELS,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    let mut buf = vec![0.0; CHUNK_FRAMES * CHANNELS as usize];
+    let mut chunks = 0;
+
+    while render(&mut source, &mixer, &stop, &mut buf) {
+        for sample in buf.iter() {
+            writer.write_sample(*sample)?;
+        }
+
+        chunks += 1;
+        let idle = mixer.is_idle();
+        if chunks == FLUSH_CHUNKS || idle {
+            chunks = 0;
+            writer.flush()?;
+        }
+        if idle {
+            mixer.notify_finished();
+        }
+    }
+
+    Ok(writer.finalize()?)
+}
+
+// Frames are written as they are encoded, the header is kept up to date the
+// same way as the WAV one
+fn write_flac(
+    mut source: Mixer,
+    mixer: MixerHandle,
+    path: &Path,
+    stop: Arc<atomic::AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A block per chunk, so a block never waits on the queue to go on
+    let mut writer = FlacWriter::create(path, FLAC_BITS, CHUNK_FRAMES, Vec::new())?;
+    let mut buf = vec![0.0; CHUNK_FRAMES * CHANNELS as usize];
+    let mut chunks = 0;
+
+    while render(&mut source, &mixer, &stop, &mut buf) {
+        writer.write(&buf)?;
+
+        chunks += 1;
+        let idle = mixer.is_idle();
+        if chunks == FLUSH_CHUNKS || idle {
+            chunks = 0;
+            writer.flush()?;
+        }
+        if idle {
+            mixer.notify_finished();
+        }
+    }
+
+    writer.flush()
+}
@@ -0,0 +1,352 @@
+use std::{
+    path::PathBuf,
+    sync::{atomic, Arc},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+
+use super::{
+    mixer::{Mixer, CHUNK_FRAMES},
+    MixerHandle,
+};
+use crate::track::CHANNELS;
+
+mod custom;
+pub use custom::{AudioSink, CustomSink, SampleFormat, Samples, SinkFormat};
+
+mod file;
+pub use file::FileFormat;
+
+mod null;
+
+mod opus;
+pub use opus::{OpusFrame, OpusSender};
+
+// How long offline outputs wait while there is nothing to render
+const IDLE: Duration = Duration::from_millis(10);
+// How long they wait for decoders that fell behind
+const DECODE_WAIT: Duration = Duration::from_millis(1);
+// Rates listed for a device when its configs cover them
+const COMMON_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDevice {
+    pub name: String,
+    pub is_default: bool,
+    pub sample_rates: Vec<u32>,
+    pub channels: Vec<u16>,
+}
+
+impl std::fmt::Display for OutputDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "OutputDevice(name: {}, default: {}, rates: {:?}, channels: {:?})",
+            self.name, self.is_default, self.sample_rates, self.channels
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Output {
+    #[default]
+    Default,
+    // Picked by name, see `AudioManager::output_devices`
+    Device(String),
+    // Throws the audio away, paced like a sound card or as fast as possible
+    Null {
+        realtime: bool,
+    },
+    // Renders as fast as decoding allows, nothing is written while idle
+    //
+    // Kept playable while written, see `AudioManager::finished` for knowing
+    // when the queue is all in
+    File {
+        path: PathBuf,
+        format: FileFormat,
+    },
+    // 20ms Opus frames for voice chat, the track's own packets when the mixer
+    // leaves them untouched and re-encoded at `bitrate` kbps otherwise
+    Opus {
+        sender: OpusSender,
+        bitrate: u32,
+    },
+    // Written to a user sink, converted to its rate, channels and sample format
+    Custom(CustomSink),
+}
+
+impl Output {
+    // Rendered as fast as possible, so decks wait for their decoder instead of
+    // filling gaps with silence
+    fn is_offline(&self) -> bool {
+        matches!(
+            *self,
+            Output::Null { realtime: false }
+                | Output::File { .. }
+                | Output::Opus { .. }
+                | Output::Custom(_)
+        )
+    }
+}
+
+impl std::fmt::Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Output::Default => write!(f, "Default"),
+            Output::Device(ref name) => write!(f, "Device(name: {name})"),
+            Output::Null { realtime } => write!(f, "Null(realtime: {realtime})"),
+            Output::File { ref path, format } => {
+                write!(f, "File(path: {}, format: {format})", path.display())
+            }
+            Output::Opus { bitrate, .. } => write!(f, "Opus(bitrate: {bitrate})"),
+            Output::Custom(ref sink) => write!(f, "Custom(format: {})", sink.format()),
+        }
+    }
+}
+
+pub(super) fn devices() -> Vec<OutputDevice> {
+    let host = rodio::cpal::default_host();
+    let default = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+    let Ok(devices) = host.output_devices().map_err(|e| dbg!(e)) else {
+        return Vec::new();
+    };
+
+    devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            let configs: Vec<_> = device.supported_output_configs().ok()?.collect();
+
+            let mut channels: Vec<_> = configs.iter().map(|config| config.channels()).collect();
+            channels.sort_unstable();
+            channels.dedup();
+
+            let sample_rates = COMMON_RATES
+                .into_iter()
+                .filter(|rate| {
+                    configs.iter().any(|config| {
+                        (config.min_sample_rate().0..=config.max_sample_rate().0).contains(rate)
+                    })
+                })
+                .collect();
+
+            Some(OutputDevice {
+                is_default: default.as_ref() == Some(&name),
+                name,
+                sample_rates,
+                channels,
+            })
+        })
+        .collect()
+}
+
+// Falls back to the default device when the requested one isn't there
+fn open_stream(output: Output) -> Option<(Output, rodio::OutputStream, rodio::OutputStreamHandle)> {
+    if let Output::Device(ref name) = output {
+        let device = rodio::cpal::default_host()
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| {
+                devices.find(|device| device.name().is_ok_and(|device| device == *name))
+            });
+
+        match device.map(|device| rodio::OutputStream::try_from_device(&device)) {
+            Some(Ok((stream, handle))) => return Some((output, stream, handle)),
+            Some(Err(e)) => log::warn!("Can't open output {name}: {e}"),
+            None => log::warn!("Output {name} not found"),
+        }
+    }
+
+    let (stream, handle) = rodio::OutputStream::try_default()
+        .map_err(|e| dbg!(e))
+        .ok()?;
+    Some((Output::Default, stream, handle))
+}
+
+// Need to make OutputStream send
+// I don't even use it. It need to be alive to keep audio alive
+struct OutS(#[allow(dead_code)] rodio::OutputStream);
+unsafe impl Send for OutS {}
+
+// Output pulling from the mixer on its own thread, stopped when dropped
+struct Worker {
+    stop: Arc<atomic::AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn(
+        mixer: &MixerHandle,
+        run: impl FnOnce(Mixer, MixerHandle, Arc<atomic::AtomicBool>) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(atomic::AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("audio output".to_string())
+            .spawn({
+                let (source, mixer, stop) = (mixer.source(), mixer.clone(), stop.clone());
+                move || run(source, mixer, stop)
+            })
+            .unwrap();
+
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Worker {
+    // Joined so it can't render a chunk after the next output started
+    fn drop(&mut self) {
+        self.stop.store(true, atomic::Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+// Waits until there is something to play and the decoders are far enough
+// ahead to render a chunk, false when stopped
+//
+// Decks of offline outputs block on their decoder while the mixer is locked,
+// so waiting out a stalled decoder has to happen here
+fn wait(mixer: &MixerHandle, stop: &atomic::AtomicBool) -> bool {
+    loop {
+        if stop.load(atomic::Ordering::Relaxed) {
+            return false;
+        }
+
+        match (mixer.is_idle(), mixer.is_ready()) {
+            (true, _) => std::thread::sleep(IDLE),
+            (false, false) => std::thread::sleep(DECODE_WAIT),
+            (false, true) => return true,
+        }
+    }
+}
+
+// Fills `buf` once there is something to play, false when stopped
+fn render(
+    source: &mut Mixer,
+    mixer: &MixerHandle,
+    stop: &atomic::AtomicBool,
+    buf: &mut [f32],
+) -> bool {
+    // A chunk at a time, which is what `wait` makes sure can be rendered
+    for chunk in buf.chunks_mut(CHUNK_FRAMES * CHANNELS as usize) {
+        if !wait(mixer, stop) {
+            return false;
+        }
+        fill(source, chunk);
+    }
+
+    !stop.load(atomic::Ordering::Relaxed)
+}
+
+fn fill(source: &mut Mixer, buf: &mut [f32]) {
+    for (sample, value) in buf.iter_mut().zip(source.by_ref()) {
+        *sample = value;
+    }
+}
+
+enum Backend {
+    Stream {
+        _stream: OutS,
+        // Pausing is done by the mixer, the sink just keeps playing
+        _sink: rodio::Sink,
+    },
+    Worker(#[allow(dead_code)] Worker),
+}
+
+// An open output playing the mixer
+pub(super) struct Device {
+    output: Output,
+    backend: Backend,
+    mixer: MixerHandle,
+    // Chunks rendered at the last presence check
+    rendered: u64,
+}
+
+impl Device {
+    // Without any sound card it plays into a real time null output
+    pub fn open(output: Output, mixer: &MixerHandle) -> Self {
+        mixer.set_blocking(output.is_offline());
+        mixer.set_passthrough(matches!(output, Output::Opus { .. }));
+
+        let worker = match output {
+            Output::Null { realtime } => Some(Worker::spawn(mixer, move |source, mixer, stop| {
+                null::run(source, mixer, realtime, stop)
+            })),
+            Output::File { ref path, format } => {
+                let path = path.clone();
+                Some(Worker::spawn(mixer, move |source, mixer, stop| {
+                    file::run(source, mixer, &path, format, stop)
+                }))
+            }
+            Output::Opus {
+                ref sender,
+                bitrate,
+            } => {
+                let sender = sender.clone();
+                Some(Worker::spawn(mixer, move |source, mixer, stop| {
+                    opus::run(source, mixer, sender, bitrate, stop)
+                }))
+            }
+            Output::Custom(ref sink) => {
+                let sink = sink.clone();
+                Some(Worker::spawn(mixer, move |source, mixer, stop| {
+                    custom::run(source, mixer, sink, stop)
+                }))
+            }
+            _ => None,
+        };
+        if let Some(worker) = worker {
+            return Self {
+                output,
+                backend: Backend::Worker(worker),
+                mixer: mixer.clone(),
+                rendered: mixer.rendered(),
+            };
+        }
+
+        let stream = open_stream(output).and_then(|(output, stream, handle)| {
+            let sink = rodio::Sink::try_new(&handle).map_err(|e| dbg!(e)).ok()?;
+            sink.append(mixer.source());
+
+            Some(Self {
+                output,
+                backend: Backend::Stream {
+                    _stream: OutS(stream),
+                    _sink: sink,
+                },
+                mixer: mixer.clone(),
+                rendered: mixer.rendered(),
+            })
+        });
+
+        stream.unwrap_or_else(|| {
+            log::warn!("No output device, playing into a null output");
+            Self::open(Output::Null { realtime: true }, mixer)
+        })
+    }
+
+    // What is actually playing, Default if the requested device wasn't there
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    // A stream keeps pulling even while paused, so a device that went away
+    // shows as nothing rendered since the last check. Nothing gets opened, so
+    // a device busy with our own stream still counts as present. The default
+    // device can go away as well, opening it again picks up the new default
+    pub fn is_present(&mut self) -> bool {
+        let rendered = std::mem::replace(&mut self.rendered, self.mixer.rendered());
+
+        match self.backend {
+            Backend::Stream { .. } => self.rendered != rendered,
+            Backend::Worker(_) => true,
+        }
+    }
+}
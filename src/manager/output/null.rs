@@ -0,0 +1,117 @@
+use std::{
+    sync::{atomic, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    manager::{
+        mixer::{Mixer, CHUNK_FRAMES},
+        MixerHandle,
+    },
+    track::{CHANNELS, SAMPLE_RATE},
+};
+
+use super::wait;
+
+pub(super) fn run(
+    mut source: Mixer,
+    mixer: MixerHandle,
+    realtime: bool,
+    stop: Arc<atomic::AtomicBool>,
+) {
+    let start = Instant::now();
+    let mut frames = 0u64;
+
+    while !stop.load(atomic::Ordering::Relaxed) {
+        if !realtime && !wait(&mixer, &stop) {
+            break;
+        }
+
+        source
+            .by_ref()
+            .take(CHUNK_FRAMES * CHANNELS as usize)
+            .for_each(drop);
+        frames += CHUNK_FRAMES as u64;
+
+        if realtime {
+            let due = start + Duration::from_secs_f64(frames as f64 / SAMPLE_RATE as f64);
+            std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use crate::{
+        manager::{
+            output::{Device, Output},
+            MixerHandle,
+        },
+        track::{TrackSource, CHANNELS, SAMPLE_RATE},
+    };
+
+    // Far longer than an offline output takes for a few seconds of audio
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn track(secs: usize) -> TrackSource {
+        TrackSource::from_samples(&vec![0.5; secs * SAMPLE_RATE as usize * CHANNELS as usize])
+    }
+
+    fn offline() -> (MixerHandle, Device) {
+        let mixer = MixerHandle::new();
+        let device = Device::open(Output::Null { realtime: false }, &mixer);
+        (mixer, device)
+    }
+
+    #[test]
+    fn plays_through_the_queue() {
+        let (mixer, _device) = offline();
+
+        let first = mixer.play(track(2));
+        let second = mixer.append(track(1));
+        first.recv_timeout(TIMEOUT).unwrap();
+        assert!(mixer.has_current());
+        second.recv_timeout(TIMEOUT).unwrap();
+
+        // Nothing is rendered once the queue is done
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(mixer.is_idle());
+        let rendered = mixer.rendered();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(mixer.rendered(), rendered);
+    }
+
+    #[test]
+    fn pause_holds_the_track_until_resumed() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (mixer, _device) = offline();
+
+        rt.block_on(mixer.pause());
+        let signal = mixer.play(track(1));
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(mixer.is_idle());
+        assert!(signal.try_recv().is_err());
+
+        rt.block_on(mixer.resume());
+        signal.recv_timeout(TIMEOUT).unwrap();
+        assert!(!mixer.has_current());
+    }
+
+    #[test]
+    fn skipping_replaces_the_current_track() {
+        let (mixer, _device) = offline();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(mixer.pause());
+        let skipped = mixer.play(track(1));
+        let played = mixer.play(track(1));
+        rt.block_on(mixer.resume());
+
+        played.recv_timeout(TIMEOUT).unwrap();
+        // Skipped tracks don't count as finished
+        assert_eq!(skipped.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    sync::{atomic, mpsc, Arc},
+    time::Duration,
+};
+
+use super::{wait, IDLE};
+use crate::{
+    encode::{opus_encoder, MAX_PACKET},
+    manager::{mixer::Mixer, MixerHandle},
+    track::{CHANNELS, PACKET_FRAMES},
+};
+
+// 20ms of stereo 48kHz Opus
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpusFrame {
+    pub data: Vec<u8>,
+    // Counts 48kHz frames since the output was opened, 960 per frame
+    pub timestamp: u64,
+    // Where in the current track the frame starts, None between tracks
+    pub position: Option<Duration>,
+    // Copied from the track without decoding, false if it was re-encoded
+    pub passthrough: bool,
+}
+
+impl std::fmt::Display for OpusFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "OpusFrame(len: {}, timestamp: {}, position: {:?}, passthrough: {})",
+            self.data.len(),
+            self.timestamp,
+            self.position,
+            self.passthrough
+        )
+    }
+}
+
+// Where an Opus output sends its frames, only equal to its own clones
+//
+// Sending waits for room, so a bounded channel paces the output
+#[derive(Clone)]
+pub struct OpusSender(Arc<mpsc::SyncSender<OpusFrame>>);
+
+impl OpusSender {
+    pub fn new(sender: mpsc::SyncSender<OpusFrame>) -> Self {
+        Self(Arc::new(sender))
+    }
+}
+
+impl PartialEq for OpusSender {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for OpusSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OpusSender")
+    }
+}
+
+pub(super) fn run(
+    mut source: Mixer,
+    mixer: MixerHandle,
+    sender: OpusSender,
+    bitrate: u32,
+    stop: Arc<atomic::AtomicBool>,
+) {
+    let Ok(mut encoder) = opus_encoder(bitrate).map_err(|e| dbg!(e)) else {
+        return;
+    };
+
+    let mut buf = vec![0.0; PACKET_FRAMES * CHANNELS as usize];
+    let mut packet = vec![0; MAX_PACKET];
+    let mut timestamp = 0;
+
+    while wait(&mixer, &stop) {
+        let (position, passthrough) = source.render_packet(&mut buf);
+        let frame = match passthrough {
+            Some(data) => OpusFrame {
+                data: data.into_vec(),
+                timestamp,
+                position,
+                passthrough: true,
+            },
+            None => match encoder.encode_float(&buf, &mut packet) {
+                Ok(len) => OpusFrame {
+                    data: packet[..len].to_vec(),
+                    timestamp,
+                    position,
+                    passthrough: false,
+                },
+                Err(e) => {
+                    log::warn!("Opus output stopped: {e}");
+                    return;
+                }
+            },
+        };
+        timestamp += PACKET_FRAMES as u64;
+
+        if !send(&sender, frame, &stop) {
+            return;
+        }
+    }
+}
+
+// Waits for room in the channel, false once stopped or nobody is listening
+fn send(sender: &OpusSender, mut frame: OpusFrame, stop: &atomic::AtomicBool) -> bool {
+    loop {
+        match sender.0.try_send(frame) {
+            Ok(()) => return true,
+            Err(mpsc::TrySendError::Full(rejected)) => frame = rejected,
+            Err(mpsc::TrySendError::Disconnected(_)) => return false,
+        }
+
+        if stop.load(atomic::Ordering::Relaxed) {
+            return false;
+        }
+        std::thread::sleep(IDLE);
+    }
+}
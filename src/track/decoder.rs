@@ -0,0 +1,301 @@
+use std::sync::{atomic, Arc, Mutex};
+
+use symphonia::core::formats::FormatReader;
+
+use super::{Track, TrackStream, CHANNELS, SAMPLE_RATE};
+use crate::dsp::Loudness;
+
+pub(super) const NO_SEEK: u64 = u64::MAX;
+
+// Bad packets in a row before the track is given up on
+pub(super) const MAX_ERRORS: usize = 16;
+// Longer gaps between packets are jumped over instead of concealed
+const MAX_GAP: u64 = SAMPLE_RATE as u64;
+// Opus conceals in steps of its frame sizes
+const GRANULE: usize = SAMPLE_RATE as usize / 400;
+const CONCEAL_FRAMES: usize = SAMPLE_RATE as usize / 50;
+// Packets are only passed on as they are when they hold 20ms, the frame size
+// voice clients expect
+pub(crate) const PACKET_FRAMES: usize = SAMPLE_RATE as usize / 50;
+
+pub(super) type Reader = Arc<Mutex<symphonia::default::formats::MkvReader>>;
+
+// A chunk of decoded audio
+pub(super) struct Decoded<'a> {
+    pub samples: &'a [f32],
+    // Frame the chunk starts at
+    pub start: u64,
+    // A seek happened since the last chunk
+    pub seeked: bool,
+    // The packet the samples were decoded from, when they are exactly that
+    // packet and it can be passed through
+    pub packet: Option<&'a [u8]>,
+}
+
+// Pulls packets from the container and decodes them, used by playback on its
+// own thread and directly by offline passes like analysis
+pub(crate) struct TrackDecoder {
+    reader: Reader,
+    decoder: opus::Decoder,
+    // Frame a seek asked for, NO_SEEK if there is none pending
+    seek_target: Arc<atomic::AtomicU64>,
+    // Decoded audio before this frame is dropped
+    skip_until: Option<u64>,
+    // Frame the next packet should start at, gaps before it get concealed
+    expected: Option<u64>,
+    concealed: Arc<atomic::AtomicU64>,
+    // Reused for every packet so decoding doesn't allocate
+    buf: Vec<f32>,
+    // 48kHz Opus, so packets can go out without decoding
+    passthrough: bool,
+    packet: Vec<u8>,
+    replay_gain: (Option<Loudness>, Option<Loudness>),
+}
+
+impl TrackDecoder {
+    pub(super) async fn new(track: &Track) -> Option<Self> {
+        let decoder = opus::Decoder::new(
+            SAMPLE_RATE,
+            match CHANNELS {
+                1 => opus::Channels::Mono,
+                _ => opus::Channels::Stereo,
+            },
+        )
+        .ok()?;
+
+        let mut reader = open_reader(track).await?;
+        let replay_gain = replay_gain(&mut reader);
+        let passthrough = reader.default_track().is_some_and(|track| {
+            track.codec_params.codec == symphonia::core::codecs::CODEC_TYPE_OPUS
+                && track.codec_params.sample_rate == Some(SAMPLE_RATE)
+        });
+
+        Some(Self {
+            reader: Arc::new(Mutex::new(reader)),
+            decoder,
+            seek_target: Arc::new(atomic::AtomicU64::new(NO_SEEK)),
+            skip_until: None,
+            expected: None,
+            concealed: Default::default(),
+            buf: Vec::new(),
+            passthrough,
+            packet: Vec::new(),
+            replay_gain,
+        })
+    }
+
+    pub(super) fn reader(&self) -> Reader {
+        self.reader.clone()
+    }
+
+    pub(super) fn seek_target(&self) -> Arc<atomic::AtomicU64> {
+        self.seek_target.clone()
+    }
+
+    // A seek came in that the next `decode` takes care of
+    pub(super) fn is_seeking(&self) -> bool {
+        self.seek_target.load(atomic::Ordering::SeqCst) != NO_SEEK
+    }
+
+    pub(super) fn concealed(&self) -> Arc<atomic::AtomicU64> {
+        self.concealed.clone()
+    }
+
+    // Track and album loudness from the ReplayGain tags
+    pub(super) fn replay_gain(&self) -> (Option<Loudness>, Option<Loudness>) {
+        self.replay_gain
+    }
+
+    // Decodes until some audio past a pending seek target is there
+    //
+    // Corrupt or missing packets are filled in by Opus concealment, only
+    // errors the container can't get past end the track
+    pub(super) fn decode(&mut self) -> Option<Decoded<'_>> {
+        let channels = CHANNELS as usize;
+        let mut seeked = false;
+        let mut errors = 0;
+
+        loop {
+            let packet = {
+                let reader = &mut *self.reader.lock().unwrap();
+
+                // Taken under the reader lock, so every packet read from here
+                // on comes from after the seek
+                let target = self.seek_target.swap(NO_SEEK, atomic::Ordering::Relaxed);
+                if target != NO_SEEK {
+                    self.decoder.reset_state().ok()?;
+                    self.skip_until = Some(target);
+                    self.expected = None;
+                    seeked = true;
+                }
+
+                match reader.next_packet() {
+                    Ok(packet) => packet,
+                    Err(e) if is_recoverable(&e) && errors < MAX_ERRORS => {
+                        log::warn!("Skipping bad packet: {e}");
+                        errors += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        if !is_end(&e) {
+                            log::warn!("Decoding stopped: {e}");
+                        }
+                        return None;
+                    }
+                }
+            };
+
+            let packet_start = packet.ts * SAMPLE_RATE as u64 / 1000;
+            let mut start = packet_start;
+            self.buf.clear();
+
+            // Packets went missing, the last bit of the gap can be recovered
+            // from the forward error correction data in this one
+            if let Some(expected) = self.expected {
+                if packet_start > expected && packet_start - expected < MAX_GAP {
+                    let gap = (packet_start - expected) as usize / GRANULE * GRANULE;
+                    let fec = gap.min(CONCEAL_FRAMES);
+                    self.conceal(gap - fec, &[]);
+                    self.conceal(fec, packet.buf());
+                    start -= gap as u64;
+                }
+            }
+
+            let offset = self.buf.len();
+            let packet_frames = self
+                .decoder
+                .get_nb_samples(packet.buf())
+                .unwrap_or(CONCEAL_FRAMES);
+            self.buf.resize(offset + packet_frames * channels, 0.0);
+
+            let decoded =
+                match self
+                    .decoder
+                    .decode_float(packet.buf(), &mut self.buf[offset..], false)
+                {
+                    Ok(frames) => {
+                        self.buf.truncate(offset + frames * channels);
+                        true
+                    }
+                    Err(e) => {
+                        log::warn!("Concealing undecodable packet: {e}");
+                        self.buf.truncate(offset);
+                        self.conceal(packet_frames / GRANULE * GRANULE, &[]);
+                        false
+                    }
+                };
+
+            let frames = self.buf.len() / channels;
+            self.expected = Some(start + frames as u64);
+            let mut skip = 0;
+
+            if let Some(skip_until) = self.skip_until.take() {
+                skip = skip_until.saturating_sub(start) as usize;
+                if skip >= frames {
+                    self.skip_until = Some(skip_until);
+                    continue;
+                }
+
+                start += skip as u64;
+            }
+
+            // Nothing concealed in front and nothing skipped
+            let whole = decoded && offset == 0 && skip == 0 && frames == PACKET_FRAMES;
+            self.packet.clear();
+            if self.passthrough && whole {
+                self.packet.extend_from_slice(packet.buf());
+            }
+
+            return Some(Decoded {
+                samples: &self.buf[skip * channels..],
+                start,
+                seeked,
+                packet: (!self.packet.is_empty()).then_some(&self.packet[..]),
+            });
+        }
+    }
+
+    // Appends `frames` of concealed audio, from FEC data if a packet is given
+    fn conceal(&mut self, frames: usize, packet: &[u8]) {
+        let channels = CHANNELS as usize;
+        let fec = !packet.is_empty();
+        let mut left = frames;
+
+        while left > 0 {
+            let len = left.min(CONCEAL_FRAMES);
+            let offset = self.buf.len();
+            self.buf.resize(offset + len * channels, 0.0);
+
+            // Silence stays in if even concealment fails
+            if let Ok(written) = self
+                .decoder
+                .decode_float(packet, &mut self.buf[offset..], fec)
+            {
+                self.buf.truncate(offset + written * channels);
+            }
+            self.concealed.fetch_add(
+                ((self.buf.len() - offset) / channels) as u64,
+                atomic::Ordering::Relaxed,
+            );
+            left -= len;
+        }
+    }
+}
+
+// Demuxer over the track's stream, shared by playback and downloads
+pub(super) async fn open_reader(track: &Track) -> Option<symphonia::default::formats::MkvReader> {
+    // Check if the link is still valid
+    // If not try to get new one
+    let stream = match TrackStream::new(&track.format).await {
+        Some(stream) => stream,
+        None => {
+            let new_track = Track::new(track.id.to_string()).await?;
+            TrackStream::new(&new_track.format).await?
+        }
+    };
+
+    let mss = symphonia::core::io::MediaSourceStream::new(Box::new(stream), Default::default());
+    symphonia::default::formats::MkvReader::try_new(mss, &Default::default())
+        .map_err(|e| dbg!(e))
+        .ok()
+}
+
+// Corrupt data can be skipped, anything else means the stream is unusable
+pub(super) fn is_recoverable(e: &symphonia::core::errors::Error) -> bool {
+    use symphonia::core::errors::Error;
+
+    match e {
+        Error::DecodeError(_) => true,
+        Error::IoError(e) => e.kind() == std::io::ErrorKind::Interrupted,
+        _ => false,
+    }
+}
+
+pub(super) fn is_end(e: &symphonia::core::errors::Error) -> bool {
+    matches!(e, symphonia::core::errors::Error::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+fn replay_gain(
+    reader: &mut symphonia::default::formats::MkvReader,
+) -> (Option<Loudness>, Option<Loudness>) {
+    let mut tags = std::collections::HashMap::new();
+    if let Some(revision) = reader.metadata().current() {
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            let value = value.trim().trim_end_matches("dB").trim_end();
+            if let Ok(value) = value.parse::<f32>() {
+                tags.insert(tag.key.to_uppercase(), value);
+            }
+        }
+    }
+
+    let loudness = |gain: &str, peak: &str| {
+        tags.get(gain)
+            .map(|gain| Loudness::from_replay_gain(*gain, tags.get(peak).copied()))
+    };
+
+    (
+        loudness("REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_TRACK_PEAK"),
+        loudness("REPLAYGAIN_ALBUM_GAIN", "REPLAYGAIN_ALBUM_PEAK"),
+    )
+}
@@ -0,0 +1,246 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use base64::Engine;
+use symphonia::core::formats::FormatReader;
+
+use super::{
+    decoder::{is_end, is_recoverable, open_reader, MAX_ERRORS},
+    Track, CHANNELS, SAMPLE_RATE,
+};
+
+// Pre-skip libopus encoders use, for streams that come without an OpusHead
+const DEFAULT_PRE_SKIP: u16 = 312;
+const MAX_COVER_BYTES: u64 = 16 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadEvent {
+    // Fraction of the track written so far
+    Progress(f32),
+    Done,
+    Failed,
+}
+
+impl std::fmt::Display for DownloadEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            DownloadEvent::Progress(value) => write!(f, "Progress(value: {value})"),
+            DownloadEvent::Done => write!(f, "Done"),
+            DownloadEvent::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+pub(super) struct Cover {
+    mime: String,
+    width: u32,
+    height: u32,
+    pub(super) data: Vec<u8>,
+}
+
+// Copies the Opus packets out of the WebM into an Ogg Opus file as they are
+pub(super) async fn download(
+    track: &Track,
+    path: &Path,
+    progress: impl FnMut(f32) + Send + 'static,
+) -> Option<()> {
+    let reader = open_reader(track).await?;
+    let track = track.clone();
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        // A missing cover doesn't fail the download
+        let cover = cover(&track);
+        remux(reader, &track, cover, &path, progress)
+    })
+    .await
+    .ok()?
+}
+
+fn remux(
+    mut reader: symphonia::default::formats::MkvReader,
+    track: &Track,
+    cover: Option<Cover>,
+    path: &PathBuf,
+    mut progress: impl FnMut(f32),
+) -> Option<()> {
+    // Matroska keeps the OpusHead as the codec private data
+    let head = match reader.default_track()?.codec_params.extra_data {
+        Some(ref data) if data.starts_with(b"OpusHead") && data.len() >= 19 => data.to_vec(),
+        _ => opus_head(DEFAULT_PRE_SKIP),
+    };
+    let pre_skip = u16::from_le_bytes([head[10], head[11]]) as u64;
+
+    let file = std::fs::File::create(path).map_err(|e| dbg!(e)).ok()?;
+    let mut writer = ogg::PacketWriter::new(std::io::BufWriter::new(file));
+    let serial = serial(&track.id);
+
+    // Both headers sit on pages of their own
+    writer
+        .write_packet(head, serial, ogg::PacketWriteEndInfo::EndPage, 0)
+        .map_err(|e| dbg!(e))
+        .ok()?;
+    writer
+        .write_packet(
+            opus_tags(track, cover.as_ref()),
+            serial,
+            ogg::PacketWriteEndInfo::EndPage,
+            0,
+        )
+        .map_err(|e| dbg!(e))
+        .ok()?;
+
+    let duration = track.duration.max(1) as f32;
+    let mut granule = pre_skip;
+    let mut reported = 0;
+    // Held back one packet so the last one can end the stream
+    let mut pending: Option<(Box<[u8]>, u64)> = None;
+    let mut errors = 0;
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => {
+                errors = 0;
+                Some(packet)
+            }
+            Err(e) if is_recoverable(&e) && errors < MAX_ERRORS => {
+                log::warn!("Skipping bad packet: {e}");
+                errors += 1;
+                continue;
+            }
+            Err(e) if is_end(&e) => None,
+            Err(e) => {
+                dbg!(e);
+                return None;
+            }
+        };
+
+        if let Some((data, granule)) = pending.take() {
+            let info = match packet {
+                Some(_) => ogg::PacketWriteEndInfo::NormalPacket,
+                None => ogg::PacketWriteEndInfo::EndStream,
+            };
+            writer
+                .write_packet(data.into_vec(), serial, info, granule)
+                .map_err(|e| dbg!(e))
+                .ok()?;
+        }
+
+        let Some(packet) = packet else {
+            break;
+        };
+
+        granule += opus::packet::get_nb_samples(packet.buf(), SAMPLE_RATE).unwrap_or(960) as u64;
+        pending = Some((packet.data, granule));
+
+        // Reported in whole percents
+        let percent = ((packet.ts as f32 / 1000.0 / duration) * 100.0).min(100.0) as u32;
+        if percent > reported {
+            reported = percent;
+            progress(percent as f32 / 100.0);
+        }
+    }
+
+    writer.into_inner().flush().map_err(|e| dbg!(e)).ok()
+}
+
+pub(super) fn opus_head(pre_skip: u16) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(CHANNELS as u8);
+    head.extend(pre_skip.to_le_bytes());
+    head.extend(SAMPLE_RATE.to_le_bytes());
+    // Output gain and channel mapping family
+    head.extend(0i16.to_le_bytes());
+    head.push(0);
+
+    head
+}
+
+pub(super) fn opus_tags(track: &Track, cover: Option<&Cover>) -> Vec<u8> {
+    let mut comments = comments(track);
+    if let Some(cover) = cover {
+        let picture = base64::engine::general_purpose::STANDARD.encode(picture(cover));
+        comments.push(format!("METADATA_BLOCK_PICTURE={picture}"));
+    }
+
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend(vorbis_comment(&comments));
+    tags
+}
+
+pub(super) fn comments(track: &Track) -> Vec<String> {
+    vec![
+        format!("TITLE={}", track.title),
+        format!("ARTIST={}", track.author),
+    ]
+}
+
+// Shared by OpusTags and the FLAC comment block
+pub(super) fn vorbis_comment(comments: &[String]) -> Vec<u8> {
+    let vendor = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+    let mut block = Vec::new();
+    block.extend((vendor.len() as u32).to_le_bytes());
+    block.extend(vendor.as_bytes());
+    block.extend((comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        block.extend((comment.len() as u32).to_le_bytes());
+        block.extend(comment.as_bytes());
+    }
+
+    block
+}
+
+// FLAC picture block, which is what Vorbis comments carry cover art in
+pub(super) fn picture(cover: &Cover) -> Vec<u8> {
+    let mut block = Vec::with_capacity(cover.data.len() + 64);
+    // Front cover
+    block.extend(3u32.to_be_bytes());
+    block.extend((cover.mime.len() as u32).to_be_bytes());
+    block.extend(cover.mime.as_bytes());
+    // No description
+    block.extend(0u32.to_be_bytes());
+    block.extend(cover.width.to_be_bytes());
+    block.extend(cover.height.to_be_bytes());
+    // Colour depth and palette size
+    block.extend(24u32.to_be_bytes());
+    block.extend(0u32.to_be_bytes());
+    block.extend((cover.data.len() as u32).to_be_bytes());
+    block.extend(&cover.data);
+
+    block
+}
+
+// The biggest thumbnail
+pub(super) fn cover(track: &Track) -> Option<Cover> {
+    let thumbnail = track
+        .thumbnails
+        .iter()
+        .max_by_key(|thumbnail| thumbnail.width * thumbnail.height)?;
+    let response = ureq::get(&thumbnail.url).call().map_err(|e| dbg!(e)).ok()?;
+
+    let mime = response.content_type().to_string();
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_COVER_BYTES)
+        .read_to_end(&mut data)
+        .map_err(|e| dbg!(e))
+        .ok()?;
+
+    Some(Cover {
+        mime,
+        width: thumbnail.width as u32,
+        height: thumbnail.height as u32,
+        data,
+    })
+}
+
+// Stable per track, any value works for a file with one stream
+pub(super) fn serial(id: &str) -> u32 {
+    id.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as u32)
+    })
+}
@@ -1,8 +1,14 @@
+mod decoder;
+use decoder::TrackDecoder;
+pub(crate) use decoder::PACKET_FRAMES;
+
 mod source;
 use std::sync::Arc;
 
-use source::TrackSource;
+use crate::dsp::{Loudness, LoudnessMeter};
+
 pub use source::TrackSourceHandle;
+pub(crate) use source::{Metadata, Packet, TrackSource, CHANNELS, SAMPLE_RATE};
 
 mod stream;
 use stream::TrackStream;
@@ -10,6 +16,15 @@ use stream::TrackStream;
 mod queue;
 pub use queue::{TracksQueue, TracksQueueHandle};
 
+mod waveform;
+pub use waveform::Waveform;
+
+mod download;
+pub use download::DownloadEvent;
+
+mod transcode;
+pub use transcode::Codec;
+
 #[derive(Debug, Clone)]
 pub struct Track {
     format: Arc<rusty_ytdl::VideoFormat>,
@@ -53,4 +68,51 @@ impl Track {
     pub async fn start(&self) -> Option<(TrackSource, TrackSourceHandle)> {
         TrackSource::new(self).await
     }
+
+    // Decoder on its own stream for offline passes, playback is left alone
+    async fn decoder(&self) -> Option<TrackDecoder> {
+        TrackDecoder::new(self).await
+    }
+
+    // Saves the original Opus stream as an Ogg Opus file with title, author
+    // and cover tags
+    pub async fn download(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        progress: impl FnMut(f32) + Send + 'static,
+    ) -> Option<()> {
+        download::download(self, path.as_ref(), progress).await
+    }
+
+    // Decodes the track and encodes it to `codec`, with the same tags as a
+    // download
+    pub async fn transcode(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        codec: Codec,
+        progress: impl FnMut(f32) + Send + 'static,
+    ) -> Option<()> {
+        transcode::transcode(self, path.as_ref(), codec, progress).await
+    }
+
+    // Starts decoding the track on `rt` into `buckets` peak pairs
+    pub fn waveform(&self, rt: &tokio::runtime::Handle, buckets: usize) -> Arc<Waveform> {
+        Waveform::start(self, rt, buckets)
+    }
+
+    // Decodes the whole track on its own stream to measure its loudness
+    pub async fn loudness(&self) -> Option<Loudness> {
+        let mut decoder = self.decoder().await?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut meter = LoudnessMeter::new(CHANNELS);
+            while let Some(decoded) = decoder.decode() {
+                meter.push(decoded.samples);
+            }
+
+            meter.loudness()
+        })
+        .await
+        .ok()?
+    }
 }
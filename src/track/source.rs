@@ -1,178 +1,459 @@
-use std::sync::{atomic, Arc, Mutex};
+use std::{
+    sync::{atomic, Arc},
+    time::Duration,
+};
 
 use symphonia::core::formats::FormatReader;
 
-use crate::Track;
+use super::decoder::{Decoded, Reader, TrackDecoder, PACKET_FRAMES};
+use crate::{dsp::Loudness, Track};
+
+// Opus always decodes at 48kHz and can up/downmix by itself, so every source
+// shares one output format and the mixer can combine them sample by sample
+pub(crate) const SAMPLE_RATE: u32 = 48000;
+pub(crate) const CHANNELS: u16 = 2;
+
+// Opus needs some audio before a seek target to converge after a reset
+const PRE_ROLL_MS: u64 = 80;
+
+// Half a second decoded ahead of playback
+const RING_SAMPLES: usize = SAMPLE_RATE as usize * CHANNELS as usize / 2;
+// How long the worker waits when the ring is full
+const IDLE: Duration = Duration::from_millis(5);
+// How long a blocking source waits for the worker
+const WAIT: Duration = Duration::from_millis(1);
+// The track time is published every 10ms of audio
+const UPDATE_SAMPLES: usize = SAMPLE_RATE as usize / 100 * CHANNELS as usize;
+// Packets kept next to the ring for passthrough, a little more than it holds
+const RING_PACKETS: usize = RING_SAMPLES / (PACKET_FRAMES * CHANNELS as usize) + 8;
+
+const RUNNING: u8 = 0;
+// The worker waits for the audio thread to drop everything from before a seek
+const FLUSH: u8 = 1;
+
+struct Shared {
+    // Milliseconds
+    current_time: atomic::AtomicU64,
+    gain: atomic::AtomicU32,
+    state: atomic::AtomicU8,
+    // Frame the audio in the ring starts at since the last flush
+    base: atomic::AtomicU64,
+    underruns: atomic::AtomicU64,
+    // The output takes Opus packets, so the worker hands them over as well
+    passthrough: atomic::AtomicBool,
+    // The worker decoded everything and waits for a seek or the source to go
+    ended: atomic::AtomicBool,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            current_time: Default::default(),
+            gain: atomic::AtomicU32::new(1f32.to_bits()),
+            state: atomic::AtomicU8::new(RUNNING),
+            base: Default::default(),
+            underruns: Default::default(),
+            passthrough: Default::default(),
+            ended: Default::default(),
+        }
+    }
+}
 
+// An undecoded packet and the frame its audio starts at
+pub(crate) struct Packet {
+    pub data: Box<[u8]>,
+    pub start: u64,
+}
+
+// Audio thread side of a track, it only copies out what a decode worker put
+// in the ring so it never locks or waits on the network
 pub struct TrackSource {
-    decoder: Arc<Mutex<opus::Decoder>>,
-    reader: Arc<Mutex<symphonia::default::formats::MkvReader>>,
-    current_time: Arc<atomic::AtomicU64>,
-    sample_buf: Vec<f32>,
-    channels: u16,
-    sample_rate: u32,
-    duration: Option<std::time::Duration>,
+    samples: rtrb::Consumer<f32>,
+    packets: rtrb::Consumer<Packet>,
+    shared: Arc<Shared>,
+    // Samples read since the last flush
+    read: u64,
+    since_update: usize,
+    starved: bool,
+    // Wait for the worker instead of playing silence, for offline outputs
+    blocking: bool,
+    duration: Option<Duration>,
 }
 
 impl TrackSource {
-    pub(super) async fn new(track: &super::Track) -> Option<(Self, TrackSourceHandle)> {
-        let format = &track.format;
-        let channels = format.audio_channels.unwrap_or(2) as u16;
-        let sample_rate = format
-            .audio_sample_rate
-            .as_ref()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(48000);
-
-        let duration = Some(std::time::Duration::from_secs(track.duration));
-
-        let decoder = opus::Decoder::new(
-            sample_rate,
-            match channels {
-                1 => opus::Channels::Mono,
-                _ => opus::Channels::Stereo,
-            },
-        )
-        .ok()?;
-
-        // Check if the link is still valid
-        // If not try to get new one
-        let stream = match super::TrackStream::new(format).await {
-            Some(stream) => stream,
-            None => {
-                let new_track = Track::new(track.id.to_string()).await?;
-                super::TrackStream::new(&new_track.format).await?
-            }
-        };
+    pub(super) async fn new(track: &Track) -> Option<(Self, TrackSourceHandle)> {
+        let mut decoder = TrackDecoder::new(track).await?;
+        let (replay_gain, album_replay_gain) = decoder.replay_gain();
 
-        let mss = symphonia::core::io::MediaSourceStream::new(Box::new(stream), Default::default());
-        let reader = symphonia::default::formats::MkvReader::try_new(mss, &Default::default())
-            .map_err(|e| dbg!(e))
-            .ok()?;
+        let shared = Arc::new(Shared::new());
+        let (mut producer, samples) = rtrb::RingBuffer::new(RING_SAMPLES);
+        let (packet_producer, packets) = rtrb::RingBuffer::new(RING_PACKETS);
 
-        let decoder = Arc::new(Mutex::new(decoder));
-        let reader = Arc::new(Mutex::new(reader));
-        let current_time = Arc::new(atomic::AtomicU64::default());
-
-        let mut source = TrackSource {
-            decoder: decoder.clone(),
-            reader: reader.clone(),
-            current_time: current_time.clone(),
-            sample_buf: Vec::new(),
-            channels,
-            sample_rate,
-            duration,
-        };
-
-        source.decode();
+        // Something is ready before the first callback
+        let decoded = decoder.decode()?;
+        shared.base.store(decoded.start, atomic::Ordering::Relaxed);
+        push(&mut producer, decoded.samples);
 
         let handle = TrackSourceHandle {
-            reader,
-            decoder,
-            current_time,
+            reader: decoder.reader(),
+            seek_target: decoder.seek_target(),
+            concealed: decoder.concealed(),
+            shared: shared.clone(),
             metadata: Arc::new(Metadata {
+                id: track.id.clone(),
                 title: track.title.clone(),
                 author: track.author.clone(),
                 thumbnails: track.thumbnails.clone(),
                 duration: track.duration,
+                replay_gain,
+                album_replay_gain,
             }),
         };
 
+        std::thread::Builder::new()
+            .name("track decoder".to_string())
+            .spawn({
+                let shared = shared.clone();
+                move || run(decoder, producer, packet_producer, shared)
+            })
+            .map_err(|e| dbg!(e))
+            .ok()?;
+
+        let source = TrackSource {
+            samples,
+            packets,
+            shared,
+            read: 0,
+            since_update: 0,
+            starved: false,
+            blocking: false,
+            duration: Some(Duration::from_secs(track.duration)),
+        };
+
         Some((source, handle))
     }
 
-    pub(super) fn decode(&mut self) -> Option<()> {
-        let Ok(packet) = self.reader.lock().unwrap().next_packet() else {
+    // Already decoded audio without a worker, for driving the mixer in tests
+    #[cfg(test)]
+    pub(crate) fn from_samples(samples: &[f32]) -> Self {
+        let (mut producer, consumer) = rtrb::RingBuffer::new(samples.len().max(1));
+        push(&mut producer, samples);
+        let (_, packets) = rtrb::RingBuffer::new(1);
+        let frames = samples.len() / CHANNELS as usize;
+
+        TrackSource {
+            samples: consumer,
+            packets,
+            shared: Arc::new(Shared::new()),
+            read: 0,
+            since_update: 0,
+            starved: false,
+            blocking: false,
+            duration: Some(Duration::from_secs_f64(frames as f64 / SAMPLE_RATE as f64)),
+        }
+    }
+
+    // Drops what was buffered before a seek and lets the worker go on
+    fn flush(&mut self) {
+        if let Ok(chunk) = self.samples.read_chunk(self.samples.slots()) {
+            chunk.commit_all();
+        }
+        while self.packets.pop().is_ok() {}
+        self.read = 0;
+        self.since_update = 0;
+        self.shared.state.store(RUNNING, atomic::Ordering::Release);
+    }
+
+    // Frame of the next sample to be read
+    pub(crate) fn frame(&self) -> u64 {
+        self.shared.base.load(atomic::Ordering::Relaxed) + self.read / CHANNELS as u64
+    }
+
+    fn update_time(&self) {
+        let frame = self.frame();
+        self.shared
+            .current_time
+            .store(frame * 1000 / SAMPLE_RATE as u64, atomic::Ordering::Relaxed);
+    }
+
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        let position = self.shared.current_time.load(atomic::Ordering::Relaxed);
+
+        self.duration
+            .map(|duration| duration.saturating_sub(Duration::from_millis(position)))
+    }
+
+    pub(crate) fn gain(&self) -> f32 {
+        f32::from_bits(self.shared.gain.load(atomic::Ordering::Relaxed))
+    }
+
+    // Enough is decoded to read `samples` without waiting on the worker, or
+    // the track ends before that. Takes care of a pending flush first, so
+    // reading after it doesn't wait either
+    pub(crate) fn is_ready(&mut self, samples: usize) -> bool {
+        if self.shared.state.load(atomic::Ordering::Acquire) == FLUSH {
+            self.flush();
+        }
+
+        self.is_finished() || self.samples.slots() >= samples.min(self.samples.buffer().capacity())
+    }
+
+    // Nothing more is coming unless the track is seeked, so an empty ring
+    // after this means everything was played
+    fn is_finished(&self) -> bool {
+        self.shared.ended.load(atomic::Ordering::SeqCst) || self.samples.is_abandoned()
+    }
+
+    // The last sample was filled in because the worker fell behind
+    pub(crate) fn is_starved(&self) -> bool {
+        self.starved
+    }
+
+    pub(crate) fn set_blocking(&mut self, blocking: bool) {
+        self.blocking = blocking;
+    }
+
+    pub(crate) fn set_passthrough(&self, passthrough: bool) {
+        self.shared
+            .passthrough
+            .store(passthrough, atomic::Ordering::Relaxed);
+    }
+
+    // Next packet whose audio was all read before `until`
+    pub(crate) fn pop_packet(&mut self, until: u64) -> Option<Packet> {
+        let packet = self.packets.peek().ok()?;
+        if packet.start + PACKET_FRAMES as u64 > until {
             return None;
-        };
+        }
 
-        self.current_time
-            .store(packet.ts, atomic::Ordering::Relaxed);
+        self.packets.pop().ok()
+    }
+}
 
-        let decoder = &mut *self.decoder.lock().unwrap();
-        let packet_size = decoder.get_nb_samples(packet.buf()).unwrap_or(1500);
-        let actual_size = packet_size * self.channels as usize;
-        let mut tmp = vec![0.0; actual_size];
+// Decode worker, stops once the source is dropped. It waits at the end of the
+// track, so seeking back from the last bit that is still playing works
+fn run(
+    mut decoder: TrackDecoder,
+    mut producer: rtrb::Producer<f32>,
+    mut packets: rtrb::Producer<Packet>,
+    shared: Arc<Shared>,
+) {
+    loop {
+        decode(&mut decoder, &mut producer, &mut packets, &shared);
+
+        shared.ended.store(true, atomic::Ordering::SeqCst);
+        while !decoder.is_seeking() {
+            if producer.is_abandoned() {
+                return;
+            }
+            std::thread::sleep(IDLE);
+        }
+        shared.ended.store(false, atomic::Ordering::SeqCst);
+    }
+}
 
-        decoder
-            .decode_float(packet.buf(), &mut tmp, false)
-            .map(|_| ())
-            .ok()?;
+// Fills the ring until the decoder runs out or the source is dropped
+fn decode(
+    decoder: &mut TrackDecoder,
+    producer: &mut rtrb::Producer<f32>,
+    packets: &mut rtrb::Producer<Packet>,
+    shared: &Shared,
+) {
+    while let Some(Decoded {
+        samples,
+        start,
+        seeked,
+        packet,
+    }) = decoder.decode()
+    {
+        if seeked {
+            shared.state.store(FLUSH, atomic::Ordering::Release);
+            while shared.state.load(atomic::Ordering::Acquire) == FLUSH {
+                if producer.is_abandoned() {
+                    return;
+                }
+                std::thread::sleep(IDLE);
+            }
+            shared.base.store(start, atomic::Ordering::Relaxed);
+        }
+
+        // Goes in ahead of its samples, so it's there once they are read. A
+        // full ring just means nobody takes them
+        if let Some(data) = packet.filter(|_| shared.passthrough.load(atomic::Ordering::Relaxed)) {
+            _ = packets.push(Packet {
+                data: data.into(),
+                start,
+            });
+        }
 
-        self.sample_buf.append(&mut tmp);
+        let mut samples = samples;
+        while !samples.is_empty() {
+            if producer.is_abandoned() {
+                return;
+            }
 
-        Some(())
+            match push(producer, samples) {
+                0 => std::thread::sleep(IDLE),
+                written => samples = &samples[written..],
+            }
+        }
     }
 }
 
+// Writes as much as fits and returns how many samples that was
+fn push(producer: &mut rtrb::Producer<f32>, samples: &[f32]) -> usize {
+    let len = producer.slots().min(samples.len());
+    let Ok(mut chunk) = producer.write_chunk(len) else {
+        return 0;
+    };
+
+    let (first, second) = chunk.as_mut_slices();
+    first.copy_from_slice(&samples[..first.len()]);
+    second.copy_from_slice(&samples[first.len()..len]);
+    chunk.commit_all();
+
+    len
+}
+
 impl Iterator for TrackSource {
     type Item = f32;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.sample_buf.len() <= self.sample_buf.capacity() / 2 {
-            self.decode()?;
-        }
+        loop {
+            if self.shared.state.load(atomic::Ordering::Acquire) == FLUSH {
+                self.flush();
+            }
 
-        Some(self.sample_buf.remove(0))
+            // Checked first, so an empty ring after it means everything was played
+            let finished = self.is_finished();
+            match self.samples.pop() {
+                Ok(sample) => {
+                    self.starved = false;
+                    self.read += 1;
+                    self.since_update += 1;
+                    if self.since_update == UPDATE_SAMPLES {
+                        self.since_update = 0;
+                        self.update_time();
+                    }
+
+                    return Some(sample);
+                }
+                Err(_) if finished => return None,
+                // Offline outputs wait for `is_ready` before rendering, this
+                // only covers reading more than that promised
+                Err(_) if self.blocking => std::thread::sleep(WAIT),
+                // The worker fell behind, play silence instead of ending the track
+                Err(_) => {
+                    if !self.starved {
+                        self.starved = true;
+                        self.shared
+                            .underruns
+                            .fetch_add(1, atomic::Ordering::Relaxed);
+                    }
+
+                    return Some(0.0);
+                }
+            }
+        }
     }
 }
 
 impl rodio::Source for TrackSource {
     fn current_frame_len(&self) -> Option<usize> {
-        Some(self.sample_buf.len())
+        None
     }
 
     fn channels(&self) -> u16 {
-        self.channels
+        CHANNELS
     }
 
     fn sample_rate(&self) -> u32 {
-        self.sample_rate
+        SAMPLE_RATE
     }
 
-    fn total_duration(&self) -> Option<std::time::Duration> {
+    fn total_duration(&self) -> Option<Duration> {
         self.duration
     }
 }
 
 #[derive(Clone)]
 pub struct TrackSourceHandle {
-    decoder: Arc<Mutex<opus::Decoder>>,
-    reader: Arc<Mutex<symphonia::default::formats::MkvReader>>,
-    current_time: Arc<atomic::AtomicU64>,
+    reader: Reader,
+    seek_target: Arc<atomic::AtomicU64>,
+    concealed: Arc<atomic::AtomicU64>,
+    shared: Arc<Shared>,
     metadata: Arc<Metadata>,
 }
 
 impl TrackSourceHandle {
-    pub(crate) fn seek(&self, sec: u64, frac: f64) {
-        self.reader
-            .lock()
-            .unwrap()
+    // Lands on the exact sample, the decoder drops whatever comes before it
+    pub(crate) fn seek(&self, pos: Duration) -> Option<()> {
+        let reader = &mut *self.reader.lock().unwrap();
+        let pre_roll = pos.saturating_sub(Duration::from_millis(PRE_ROLL_MS));
+
+        reader
             .seek(
-                symphonia::core::formats::SeekMode::Coarse,
+                symphonia::core::formats::SeekMode::Accurate,
                 symphonia::core::formats::SeekTo::Time {
-                    time: symphonia::core::units::Time { seconds: sec, frac },
+                    time: symphonia::core::units::Time {
+                        seconds: pre_roll.as_secs(),
+                        frac: pre_roll.subsec_nanos() as f64 / 1e9,
+                    },
                     track_id: None,
                 },
             )
-            .unwrap();
+            .map_err(|e| dbg!(e))
+            .ok()?;
 
-        self.decoder.lock().unwrap().reset_state().unwrap();
+        let target = pos.as_nanos() * SAMPLE_RATE as u128 / 1_000_000_000;
+        self.seek_target
+            .store(target as u64, atomic::Ordering::SeqCst);
+        // The worker may be waiting at the end, the source shouldn't end
+        // before it got going again
+        self.shared.ended.store(false, atomic::Ordering::SeqCst);
+        self.shared
+            .current_time
+            .store(pos.as_millis() as u64, atomic::Ordering::Relaxed);
+
+        Some(())
     }
 
     pub fn current_time(&self) -> u64 {
-        self.current_time.load(atomic::Ordering::Relaxed) / 1000
+        self.shared.current_time.load(atomic::Ordering::Relaxed) / 1000
+    }
+
+    pub fn position(&self) -> Duration {
+        Duration::from_millis(self.shared.current_time.load(atomic::Ordering::Relaxed))
+    }
+
+    // Times playback ran out of decoded audio
+    pub fn underruns(&self) -> u64 {
+        self.shared.underruns.load(atomic::Ordering::Relaxed)
+    }
+
+    // Frames filled in by packet loss concealment
+    pub fn concealed_frames(&self) -> u64 {
+        self.concealed.load(atomic::Ordering::Relaxed)
     }
 
     pub fn metadata(&self) -> Arc<Metadata> {
         self.metadata.clone()
     }
+
+    pub(crate) fn set_gain(&self, gain: f32) {
+        self.shared
+            .gain
+            .store(gain.to_bits(), atomic::Ordering::Relaxed);
+    }
 }
 
 pub struct Metadata {
+    pub id: Arc<str>,
     pub title: Arc<str>,
     pub author: Arc<str>,
     pub thumbnails: Arc<[rusty_ytdl::Thumbnail]>,
     pub duration: u64,
+    pub replay_gain: Option<Loudness>,
+    pub album_replay_gain: Option<Loudness>,
 }
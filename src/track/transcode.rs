@@ -0,0 +1,292 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    download::{comments, cover, opus_head, opus_tags, picture, serial, vorbis_comment, Cover},
+    Track, TrackSource, TrackSourceHandle, CHANNELS, SAMPLE_RATE,
+};
+use crate::encode::{opus_encoder, FlacWriter, MAX_PACKET};
+
+// 20ms, the frame size Opus is tuned for
+const OPUS_FRAME: usize = SAMPLE_RATE as usize / 50;
+const MP3_CHUNK_FRAMES: usize = 1152;
+const FLAC_BITS: usize = 16;
+// flacenc's default block size
+const FLAC_BLOCK_FRAMES: usize = 4096;
+
+const MP3_BITRATES: [mp3lame_encoder::Bitrate; 16] = {
+    use mp3lame_encoder::Bitrate::*;
+    [
+        Kbps8, Kbps16, Kbps24, Kbps32, Kbps40, Kbps48, Kbps64, Kbps80, Kbps96, Kbps112, Kbps128,
+        Kbps160, Kbps192, Kbps224, Kbps256, Kbps320,
+    ]
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    // Bitrate in kbps, 6 to 510
+    Opus { bitrate: u32 },
+    // Constant bitrate in kbps, rounded down to one MP3 allows
+    Mp3 { bitrate: u32 },
+    // 16 bit lossless
+    Flac,
+}
+
+impl Codec {
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Codec::Opus { .. } => "opus",
+            Codec::Mp3 { .. } => "mp3",
+            Codec::Flac => "flac",
+        }
+    }
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Codec::Opus { bitrate } => write!(f, "Opus(bitrate: {bitrate})"),
+            Codec::Mp3 { bitrate } => write!(f, "Mp3(bitrate: {bitrate})"),
+            Codec::Flac => write!(f, "Flac"),
+        }
+    }
+}
+
+// Decoded audio pulled out of a blocking source, reporting how far it got
+struct Input<P: FnMut(f32)> {
+    source: TrackSource,
+    handle: TrackSourceHandle,
+    duration: f32,
+    reported: u32,
+    progress: P,
+}
+
+impl<P: FnMut(f32)> Input<P> {
+    // Fills the start of `buf`, returns how many samples that was, 0 at the end
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let mut len = 0;
+        for (sample, value) in buf.iter_mut().zip(self.source.by_ref()) {
+            *sample = value;
+            len += 1;
+        }
+
+        // Reported in whole percents
+        let position = self.handle.position().as_secs_f32();
+        let percent = ((position / self.duration) * 100.0).min(100.0) as u32;
+        if percent > self.reported {
+            self.reported = percent;
+            (self.progress)(percent as f32 / 100.0);
+        }
+
+        len
+    }
+}
+
+// Decodes the track and encodes it again, tagged like a download
+pub(super) async fn transcode(
+    track: &Track,
+    path: &Path,
+    codec: Codec,
+    progress: impl FnMut(f32) + Send + 'static,
+) -> Option<()> {
+    let (mut source, handle) = TrackSource::new(track).await?;
+    // Nothing plays it, so there is no reason to ever fill in silence
+    source.set_blocking(true);
+
+    let track = track.clone();
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let cover = cover(&track);
+        let input = Input {
+            source,
+            handle,
+            duration: track.duration.max(1) as f32,
+            reported: 0,
+            progress,
+        };
+
+        match codec {
+            Codec::Opus { bitrate } => encode_opus(input, &track, cover, &path, bitrate),
+            Codec::Mp3 { bitrate } => encode_mp3(input, &track, cover, &path, bitrate),
+            Codec::Flac => encode_flac(input, &track, cover, &path),
+        }
+    })
+    .await
+    .ok()?
+}
+
+fn encode_opus(
+    mut input: Input<impl FnMut(f32)>,
+    track: &Track,
+    cover: Option<Cover>,
+    path: &PathBuf,
+    bitrate: u32,
+) -> Option<()> {
+    let mut encoder = opus_encoder(bitrate).map_err(|e| dbg!(e)).ok()?;
+    let pre_skip = encoder.get_lookahead().map_err(|e| dbg!(e)).ok()? as u64;
+
+    let file = std::fs::File::create(path).map_err(|e| dbg!(e)).ok()?;
+    let mut writer = ogg::PacketWriter::new(std::io::BufWriter::new(file));
+    let serial = serial(&track.id);
+
+    writer
+        .write_packet(
+            opus_head(pre_skip as u16),
+            serial,
+            ogg::PacketWriteEndInfo::EndPage,
+            0,
+        )
+        .map_err(|e| dbg!(e))
+        .ok()?;
+    writer
+        .write_packet(
+            opus_tags(track, cover.as_ref()),
+            serial,
+            ogg::PacketWriteEndInfo::EndPage,
+            0,
+        )
+        .map_err(|e| dbg!(e))
+        .ok()?;
+
+    let mut frame = vec![0.0; OPUS_FRAME * CHANNELS as usize];
+    let mut packet = vec![0; MAX_PACKET];
+    // Frames of the track read so far and frames encoded so far
+    let mut frames = 0;
+    let mut encoded = 0;
+
+    // The encoder lags `pre_skip` frames behind, so silence is fed after the
+    // end until the last of the track has come out
+    loop {
+        let len = input.read(&mut frame);
+        frame[len..].fill(0.0);
+        frames += (len / CHANNELS as usize) as u64;
+        encoded += OPUS_FRAME as u64;
+
+        let last = len < frame.len() && encoded >= frames + pre_skip;
+        let size = encoder
+            .encode_float(&frame, &mut packet)
+            .map_err(|e| dbg!(e))
+            .ok()?;
+
+        // The last granule position trims the padding off again
+        let (info, granule) = match last {
+            true => (ogg::PacketWriteEndInfo::EndStream, frames + pre_skip),
+            false => (ogg::PacketWriteEndInfo::NormalPacket, encoded),
+        };
+        writer
+            .write_packet(packet[..size].to_vec(), serial, info, granule)
+            .map_err(|e| dbg!(e))
+            .ok()?;
+
+        if last {
+            break;
+        }
+    }
+
+    writer.into_inner().flush().map_err(|e| dbg!(e)).ok()
+}
+
+fn encode_mp3(
+    mut input: Input<impl FnMut(f32)>,
+    track: &Track,
+    cover: Option<Cover>,
+    path: &PathBuf,
+    bitrate: u32,
+) -> Option<()> {
+    let mut builder = mp3lame_encoder::Builder::new()?;
+    builder
+        .set_num_channels(CHANNELS as u8)
+        .map_err(|e| dbg!(e))
+        .ok()?;
+    builder
+        .set_sample_rate(SAMPLE_RATE)
+        .map_err(|e| dbg!(e))
+        .ok()?;
+    let brate = MP3_BITRATES
+        .into_iter()
+        .rfind(|brate| *brate as u32 <= bitrate)
+        .unwrap_or(MP3_BITRATES[0]);
+    builder.set_brate(brate).map_err(|e| dbg!(e)).ok()?;
+    builder
+        .set_quality(mp3lame_encoder::Quality::NearBest)
+        .map_err(|e| dbg!(e))
+        .ok()?;
+
+    // LAME only takes small covers, the file is still written without one
+    let tag = mp3lame_encoder::Id3Tag {
+        title: track.title.as_bytes(),
+        artist: track.author.as_bytes(),
+        album: &[],
+        album_art: &[],
+        year: &[],
+        comment: &[],
+    };
+    let cover = cover.filter(|cover| cover.data.len() <= mp3lame_encoder::MAX_ALBUM_ART_SIZE);
+    let with_cover = mp3lame_encoder::Id3Tag {
+        album_art: cover.as_ref().map_or(&[], |cover| &cover.data),
+        ..tag
+    };
+    if builder.set_id3_tag(with_cover).is_err() {
+        builder.set_id3_tag(tag).map_err(|e| dbg!(e)).ok()?;
+    }
+
+    let mut encoder = builder.build().map_err(|e| dbg!(e)).ok()?;
+    let file = std::fs::File::create(path).map_err(|e| dbg!(e)).ok()?;
+    let mut file = std::io::BufWriter::new(file);
+
+    let mut buf = vec![0.0; MP3_CHUNK_FRAMES * CHANNELS as usize];
+    let mut out = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(MP3_CHUNK_FRAMES));
+
+    loop {
+        let len = input.read(&mut buf);
+        out.clear();
+        match len {
+            0 => encoder.flush_to_vec::<mp3lame_encoder::FlushNoGap>(&mut out),
+            len => encoder.encode_to_vec(mp3lame_encoder::InterleavedPcm(&buf[..len]), &mut out),
+        }
+        .map_err(|e| dbg!(e))
+        .ok()?;
+        file.write_all(&out).map_err(|e| dbg!(e)).ok()?;
+
+        if len == 0 {
+            break;
+        }
+    }
+
+    file.flush().map_err(|e| dbg!(e)).ok()
+}
+
+// Written a block at a time, the header is only complete once finished
+fn encode_flac(
+    mut input: Input<impl FnMut(f32)>,
+    track: &Track,
+    cover: Option<Cover>,
+    path: &Path,
+) -> Option<()> {
+    // Metadata block types
+    const VORBIS_COMMENT: u8 = 4;
+    const PICTURE: u8 = 6;
+
+    let mut metadata = vec![(VORBIS_COMMENT, vorbis_comment(&comments(track)))];
+    if let Some(cover) = cover {
+        metadata.push((PICTURE, picture(&cover)));
+    }
+
+    let mut writer = FlacWriter::create(path, FLAC_BITS, FLAC_BLOCK_FRAMES, metadata)
+        .map_err(|e| dbg!(e))
+        .ok()?;
+    let mut buf = vec![0.0; FLAC_BLOCK_FRAMES * CHANNELS as usize];
+
+    loop {
+        let len = input.read(&mut buf);
+        if len == 0 {
+            break;
+        }
+        writer.write(&buf[..len]).map_err(|e| dbg!(e)).ok()?;
+    }
+
+    writer.flush().map_err(|e| dbg!(e)).ok()
+}
@@ -0,0 +1,105 @@
+use std::sync::{atomic, Arc, Mutex};
+
+use super::{Track, TrackDecoder, CHANNELS, SAMPLE_RATE};
+
+// Min/max peak overview of a track, filled in while it is being decoded
+pub struct Waveform {
+    buckets: usize,
+    peaks: Mutex<Vec<(f32, f32)>>,
+    progress: atomic::AtomicU32,
+    done: atomic::AtomicBool,
+}
+
+impl Waveform {
+    pub(super) fn start(track: &Track, rt: &tokio::runtime::Handle, buckets: usize) -> Arc<Self> {
+        let waveform = Arc::new(Self {
+            buckets: buckets.max(1),
+            peaks: Mutex::new(Vec::with_capacity(buckets)),
+            progress: atomic::AtomicU32::new(0f32.to_bits()),
+            done: atomic::AtomicBool::new(false),
+        });
+
+        let track = track.clone();
+        let result = waveform.clone();
+        rt.spawn(async move {
+            if let Some(decoder) = track.decoder().await {
+                let frames = track.duration.max(1) * SAMPLE_RATE as u64;
+                let waveform = result.clone();
+                _ = tokio::task::spawn_blocking(move || waveform.decode(decoder, frames)).await;
+            }
+
+            result.done.store(true, atomic::Ordering::Release);
+        });
+
+        waveform
+    }
+
+    fn decode(&self, mut decoder: TrackDecoder, frames: u64) {
+        let bucket_frames = (frames / self.buckets as u64).max(1);
+        let channels = CHANNELS as usize;
+        let mut peak = (0.0f32, 0.0f32);
+        let mut samples = 0;
+        let mut frames = 0;
+
+        while let Some(decoded) = decoder.decode() {
+            for &sample in decoded.samples {
+                peak = (peak.0.min(sample), peak.1.max(sample));
+
+                samples += 1;
+                if samples < channels {
+                    continue;
+                }
+                samples = 0;
+                frames += 1;
+
+                if frames == bucket_frames {
+                    frames = 0;
+                    let mut peaks = self.peaks.lock().unwrap();
+                    // Track durations are rounded, so the tail goes into the last bucket
+                    match peaks.len() < self.buckets {
+                        true => peaks.push(peak),
+                        false => {
+                            let last = peaks.last_mut().unwrap();
+                            *last = (last.0.min(peak.0), last.1.max(peak.1));
+                        }
+                    }
+                    peak = (0.0, 0.0);
+
+                    let progress = (peaks.len() as f32 / self.buckets as f32).min(1.0);
+                    self.progress
+                        .store(progress.to_bits(), atomic::Ordering::Relaxed);
+                }
+            }
+        }
+
+        let mut peaks = self.peaks.lock().unwrap();
+        if peak != (0.0, 0.0) && peaks.len() < self.buckets {
+            peaks.push(peak);
+        }
+        self.progress
+            .store(1f32.to_bits(), atomic::Ordering::Relaxed);
+    }
+
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    // Buckets decoded so far as (min, max) pairs
+    pub fn peaks(&self) -> Vec<(f32, f32)> {
+        self.peaks.lock().unwrap().clone()
+    }
+
+    pub fn progress(&self) -> f32 {
+        f32::from_bits(self.progress.load(atomic::Ordering::Relaxed))
+    }
+
+    // True once decoding stopped, also when it failed part way
+    pub fn is_done(&self) -> bool {
+        self.done.load(atomic::Ordering::Acquire)
+    }
+
+    // Done without a single bucket, the track couldn't be decoded
+    pub fn is_failed(&self) -> bool {
+        self.is_done() && self.peaks.lock().unwrap().is_empty()
+    }
+}